@@ -0,0 +1,354 @@
+use std::fmt::Display;
+
+use crate::redact::REDACTED;
+
+/// Locales the game is translated in, as given to -epiclocale.
+const SUPPORTED_LOCALES: [&str; 16] = [
+    "ar", "de", "en", "es", "es-419", "fr", "id", "it", "ja", "ko", "pl", "pt-BR", "ru", "th", "tr", "zh-Hans",
+];
+
+/// Locale of the game matching the locale of the system, if the game is translated in it.
+pub fn system_locale() -> Option<String> {
+    let locale = sys_locale::get_locale()?.replace('_', "-");
+    let language = locale.split('-').next()?.to_ascii_lowercase();
+
+    let matching = match language.as_str() {
+        "pt" => "pt-BR",
+        "zh" => "zh-Hans",
+        "es" if !locale.eq_ignore_ascii_case("es-ES") && locale.contains('-') => "es-419",
+        language => language,
+    };
+
+    SUPPORTED_LOCALES
+        .iter()
+        .find(|supported| supported.eq_ignore_ascii_case(matching))
+        .map(|supported| supported.to_string())
+}
+
+/// Arguments holding credentials, masked by `LaunchArguments::redacted`.
+const SECRET_ARGUMENTS: [&str; 2] = ["AUTH_PASSWORD", "caldera"];
+
+/// Changes made to the generated command line, from an account profile or the command line.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct LaunchOptions {
+    /// Arguments added to the command line, replacing the generated ones with the same name.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_arguments: Vec<String>,
+    /// Names of the generated arguments to remove, without the leading dash.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed_arguments: Vec<String>,
+    /// Value of -epiclocale, detected from the system when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    /// Value of -epicenv, Prod when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+}
+
+impl LaunchOptions {
+    /// Applies the options to `arguments` : the locale and environment, then the removed arguments,
+    /// then the extra ones. Applying several options in a row gives precedence to the last ones.
+    pub fn apply(&self, arguments: &mut LaunchArguments) {
+        if let Some(locale) = &self.locale {
+            arguments.set("epiclocale", Some(locale));
+        }
+        if let Some(environment) = &self.environment {
+            arguments.set("epicenv", Some(environment));
+        }
+
+        for name in &self.removed_arguments {
+            arguments.remove(name.trim_start_matches('-'));
+        }
+
+        for extra in &self.extra_arguments {
+            arguments.merge(LaunchArguments::parse(extra));
+        }
+    }
+}
+
+/// Single argument of the game command line, `-name` or `-name=value`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LaunchArgument {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl LaunchArgument {
+    fn is(&self, name: &str) -> bool {
+        //unreal compares the switches without case
+        self.name.eq_ignore_ascii_case(name)
+    }
+}
+
+impl Display for LaunchArgument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "-{}={}", self.name, value),
+            None => write!(f, "-{}", self.name),
+        }
+    }
+}
+
+/// Arguments given to the game, in order. Setting an argument which is already present
+/// replaces its value in place, names are compared without case.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LaunchArguments {
+    arguments: Vec<LaunchArgument>,
+    /// Arguments which don't start with a dash, kept after the others.
+    positional: Vec<String>,
+}
+
+/// Splits a command line like windows does : on whitespace, except between double quotes.
+fn split_command_line(command_line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_token = false;
+    let mut quoted = false;
+
+    for c in command_line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_token = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_token {
+                    tokens.push(std::mem::take(&mut current));
+                    in_token = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_token = true;
+            }
+        }
+    }
+
+    if in_token {
+        tokens.push(current);
+    }
+
+    tokens
+}
+
+fn quote(token: &str) -> String {
+    if token.is_empty() || token.contains(char::is_whitespace) {
+        format!("\"{}\"", token)
+    } else {
+        token.to_string()
+    }
+}
+
+impl LaunchArguments {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a command line, as written by `to_string` or the Epic Games Launcher.
+    /// A leading executable path is skipped.
+    pub fn parse(command_line: &str) -> Self {
+        let mut arguments = Self::new();
+        let mut tokens = split_command_line(command_line).into_iter().peekable();
+
+        if tokens
+            .peek()
+            .is_some_and(|token| !token.starts_with('-') && token.to_ascii_lowercase().ends_with(".exe"))
+        {
+            tokens.next();
+        }
+
+        for token in tokens {
+            arguments.push_token(&token);
+        }
+
+        arguments
+    }
+
+    fn push_token(&mut self, token: &str) {
+        match token.strip_prefix('-') {
+            Some(argument) => match argument.split_once('=') {
+                Some((name, value)) => self.set(name, Some(value)),
+                None => self.set(argument, None),
+            },
+            None => self.positional.push(token.to_string()),
+        }
+    }
+
+    /// Adds `-name`, or removes the value of an existing `name` argument.
+    pub fn flag(mut self, name: &str) -> Self {
+        self.set(name, None);
+        self
+    }
+
+    /// Adds `-name=value`, or replaces the value of an existing `name` argument.
+    pub fn value(mut self, name: &str, value: &str) -> Self {
+        self.set(name, Some(value));
+        self
+    }
+
+    /// Removes the `name` argument if it is present.
+    pub fn without(mut self, name: &str) -> Self {
+        self.remove(name);
+        self
+    }
+
+    pub fn set(&mut self, name: &str, value: Option<&str>) {
+        let value = value.map(String::from);
+
+        match self.arguments.iter_mut().find(|argument| argument.is(name)) {
+            Some(argument) => argument.value = value,
+            None => self.arguments.push(LaunchArgument {
+                name: name.to_string(),
+                value,
+            }),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<LaunchArgument> {
+        let index = self.arguments.iter().position(|argument| argument.is(name))?;
+
+        Some(self.arguments.remove(index))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LaunchArgument> {
+        self.arguments.iter().find(|argument| argument.is(name))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &LaunchArgument> {
+        self.arguments.iter()
+    }
+
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    /// Sets every argument of `other` and appends its positional arguments.
+    pub fn merge(&mut self, other: LaunchArguments) {
+        for argument in other.arguments {
+            self.set(&argument.name, argument.value.as_deref());
+        }
+        self.positional.extend(other.positional);
+    }
+
+    /// Copy of the arguments with the credentials masked : the exchange code, the caldera token,
+    /// and any value which looks like an epic token.
+    pub fn redacted(&self) -> LaunchArguments {
+        let mut redacted = self.clone();
+
+        for argument in redacted.arguments.iter_mut() {
+            let is_secret = SECRET_ARGUMENTS.iter().any(|name| argument.is(name))
+                || argument.value.as_ref().is_some_and(|value| value.starts_with("eg1~"));
+
+            if is_secret && argument.value.is_some() {
+                argument.value = Some(REDACTED.to_string());
+            }
+        }
+
+        redacted
+    }
+
+    /// Arguments to give to `Command::args`, one string per argument.
+    pub fn to_args(&self) -> Vec<String> {
+        self.arguments
+            .iter()
+            .map(LaunchArgument::to_string)
+            .chain(self.positional.iter().cloned())
+            .collect()
+    }
+}
+
+impl Display for LaunchArguments {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let command_line = self
+            .to_args()
+            .iter()
+            .map(|argument| quote(argument))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        write!(f, "{}", command_line)
+    }
+}
+
+impl From<&str> for LaunchArguments {
+    fn from(command_line: &str) -> Self {
+        Self::parse(command_line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_prints_command_line() {
+        let command_line = "-AUTH_LOGIN=unused -AUTH_TYPE=exchangecode -epicportal \"-epicusername=Some Name\" map";
+        let arguments = LaunchArguments::parse(command_line);
+
+        assert_eq!(arguments.get("AUTH_TYPE").unwrap().value.as_deref(), Some("exchangecode"));
+        assert_eq!(arguments.get("epicportal").unwrap().value, None);
+        assert_eq!(arguments.get("epicusername").unwrap().value.as_deref(), Some("Some Name"));
+        assert_eq!(arguments.positional(), ["map"]);
+        assert_eq!(arguments.to_string(), command_line);
+        assert_eq!(LaunchArguments::parse(&arguments.to_string()), arguments);
+    }
+
+    #[test]
+    fn skips_executable() {
+        let arguments = LaunchArguments::parse("\"C:\\Program Files\\Fortnite\\FortniteLauncher.exe\" -epicportal");
+
+        assert_eq!(arguments.to_args(), ["-epicportal"]);
+        assert!(arguments.positional().is_empty());
+    }
+
+    #[test]
+    fn compares_names_without_case() {
+        let mut arguments = LaunchArguments::new().value("epiclocale", "en").flag("epicportal");
+
+        arguments.set("EpicLocale", Some("fr"));
+        assert_eq!(arguments.to_args(), ["-epiclocale=fr", "-epicportal"]);
+
+        assert!(arguments.remove("EPICPORTAL").is_some());
+        assert!(!arguments.contains("epicportal"));
+        assert!(arguments.remove("epicportal").is_none());
+    }
+
+    #[test]
+    fn merges_arguments() {
+        let mut arguments = LaunchArguments::parse("-epicenv=Prod -epicportal first");
+        arguments.merge(LaunchArguments::parse("-EPICENV=Gamedev second"));
+
+        assert_eq!(arguments.to_args(), ["-epicenv=Gamedev", "-epicportal", "first", "second"]);
+    }
+
+    #[test]
+    fn applies_options() {
+        let mut arguments = LaunchArguments::parse("-epiclocale=en -epicenv=Prod -nobe");
+        let options = LaunchOptions {
+            extra_arguments: vec![String::from("-nosplash -epicenv=Gamedev")],
+            removed_arguments: vec![String::from("-nobe")],
+            locale: Some(String::from("de")),
+            environment: Some(String::from("Stage")),
+        };
+        options.apply(&mut arguments);
+
+        assert_eq!(arguments.to_args(), ["-epiclocale=de", "-epicenv=Gamedev", "-nosplash"]);
+    }
+
+    #[test]
+    fn redacts_credentials() {
+        let arguments = LaunchArguments::parse("-AUTH_PASSWORD=0123abcd -caldera=eyJhbGciOi.e30.sig -epicsandboxid=fn -token=eg1~abc -nobe");
+        let redacted = arguments.redacted();
+
+        assert_eq!(redacted.get("AUTH_PASSWORD").unwrap().value.as_deref(), Some(REDACTED));
+        assert_eq!(redacted.get("caldera").unwrap().value.as_deref(), Some(REDACTED));
+        assert_eq!(redacted.get("token").unwrap().value.as_deref(), Some(REDACTED));
+        assert_eq!(redacted.get("epicsandboxid").unwrap().value.as_deref(), Some("fn"));
+        assert_eq!(redacted.get("nobe").unwrap().value, None);
+        assert_eq!(arguments.get("AUTH_PASSWORD").unwrap().value.as_deref(), Some("0123abcd"));
+    }
+}
@@ -0,0 +1,185 @@
+use std::path::PathBuf;
+
+use clap::{ArgAction, Args, Parser, Subcommand};
+
+use fortnite_launcher::{
+    arguments::LaunchOptions,
+    epic::{AuthentificationType, CalderaOptions},
+    import::ImportSource,
+};
+
+#[derive(Parser)]
+#[command(version, about = "Third party launcher for Fortnite")]
+pub struct Cli {
+    /// Defaults to `launch` when no command is given.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Configuration file, defaults to FNL_CONFIG or to config.json in the configuration folder of the system
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Print more logs, repeat for even more (-v info, -vv debug, -vvv trace)
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+    /// Ask which account to use instead of using the default one, when no account is given
+    #[arg(long, global = true)]
+    pub pick: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Manage the stored accounts
+    #[command(subcommand)]
+    Accounts(AccountsCommand),
+    /// Login into an account and cache its session
+    Login {
+        /// Profile name, display name or account id, defaults to the default account (see --pick)
+        account: Option<String>,
+    },
+    /// Revoke the device auth of an account and forget it
+    Logout {
+        /// Profile name, display name or account id, uses the default account if missing
+        account: Option<String>,
+        /// Also kill every session of the account, on every device
+        #[arg(long)]
+        everywhere: bool,
+    },
+    /// Print the identity of an account as verified by epic
+    Whoami {
+        /// Profile name, display name or account id, defaults to the default account (see --pick)
+        account: Option<String>,
+    },
+    /// Decode a token without verifying it, the access token of an account if none is given
+    InspectToken {
+        /// Access token, caldera token or any other JWT
+        token: Option<String>,
+        /// Profile name, display name or account id, defaults to the default account (see --pick)
+        #[arg(long, conflicts_with = "token")]
+        account: Option<String>,
+        /// Inspect the caldera token of the account instead of its access token
+        #[arg(long, conflicts_with = "token")]
+        caldera: bool,
+        #[command(flatten)]
+        caldera_options: CalderaArgs,
+    },
+    /// Login into an account and start Fortnite
+    Launch {
+        /// Profile name, display name or account id, defaults to the default account (see --pick)
+        account: Option<String>,
+        #[command(flatten)]
+        caldera_options: CalderaArgs,
+        #[command(flatten)]
+        launch_options: LaunchArgs,
+        /// Print what would be started instead of starting it
+        #[arg(long)]
+        dry_run: bool,
+        /// Print the credentials given to the game instead of masking them
+        #[arg(long, requires = "dry_run")]
+        show_secrets: bool,
+    },
+}
+
+/// Overrides the launch options of the account profile.
+#[derive(Args, Default)]
+pub struct LaunchArgs {
+    /// Arguments added to the command line of the game, replacing the generated ones with the same name
+    #[arg(long = "arg", value_name = "ARGUMENT", allow_hyphen_values = true)]
+    pub extra_arguments: Vec<String>,
+    /// Name of a generated argument to remove from the command line of the game
+    #[arg(long = "remove-arg", value_name = "NAME", allow_hyphen_values = true)]
+    pub removed_arguments: Vec<String>,
+    /// Language of the game, detected from the system by default
+    #[arg(long)]
+    pub locale: Option<String>,
+    /// Epic environment, Prod by default
+    #[arg(long)]
+    pub environment: Option<String>,
+}
+
+impl From<LaunchArgs> for LaunchOptions {
+    fn from(args: LaunchArgs) -> Self {
+        LaunchOptions {
+            extra_arguments: args.extra_arguments,
+            removed_arguments: args.removed_arguments,
+            locale: args.locale,
+            environment: args.environment,
+        }
+    }
+}
+
+/// Overrides the caldera options of the account profile.
+#[derive(Args, Default)]
+pub struct CalderaArgs {
+    /// Epic app sent to caldera, fortnite by default
+    #[arg(long)]
+    pub epic_app: Option<String>,
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub test_mode: Option<bool>,
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub nvidia: Option<bool>,
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub luna: Option<bool>,
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub salmon: Option<bool>,
+}
+
+impl From<CalderaArgs> for CalderaOptions {
+    fn from(args: CalderaArgs) -> Self {
+        CalderaOptions {
+            epic_app: args.epic_app,
+            test_mode: args.test_mode,
+            nvidia: args.nvidia,
+            luna: args.luna,
+            salmon: args.salmon,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum AccountsCommand {
+    /// Add an account
+    Add {
+        /// Profile name, defaults to the display name of the account
+        #[arg(long)]
+        name: Option<String>,
+        /// authorization-code or device-code, prompts if missing
+        #[arg(long)]
+        method: Option<AuthentificationType>,
+        /// Authorization code to use with the authorization-code method
+        #[arg(long)]
+        code: Option<String>,
+    },
+    /// List the stored accounts
+    List,
+    /// Remove a stored account
+    Remove {
+        account: String,
+    },
+    /// Set the account used when none is specified
+    Default {
+        account: String,
+    },
+    /// List the device auths of an account
+    Devices {
+        /// Uses the default account if missing
+        account: Option<String>,
+    },
+    /// Revoke a device auth of an account
+    Revoke {
+        device_id: String,
+        /// Uses the default account if missing
+        #[arg(long)]
+        account: Option<String>,
+    },
+    /// Replace the stored device auth of an account by a new one and revoke the old one
+    Rotate {
+        /// Uses the default account if missing
+        account: Option<String>,
+    },
+    /// Import the accounts of another launcher : legendary, heroic or llama-launcher
+    Import {
+        source: ImportSource,
+        /// File holding the credentials, defaults to the one of the launcher (required for llama-launcher)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+}
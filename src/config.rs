@@ -1,52 +1,788 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+
+use crate::{arguments::LaunchOptions, error::LauncherError, migrations, redact::{self, HasSecrets}, launcher::{AntiCheatBinary, AntiCheatRegistry}, rest::{EpicContext, EpicEndpoints, RetryPolicy}};
+use crate::secrets::{SecretBackend, SecretRef, SecretStores};
+use crate::epic::{
+    AccountDetails, CalderaOptions, Client, ClientRegistry, HasClient, HasIdentity, HasRefreshToken, HasToken,
+    LoginClients, FORTNITE_IOS_GAME_CLIENT, FORTNITE_NEW_SWITCH_GAME_CLIENT, LAUNCHER_APP_CLIENT_2,
+};
+
+/// Environment variable holding the path of the configuration, overridden by `--config`.
+pub const CONFIG_ENV: &str = "FNL_CONFIG";
+const CONFIG_FILE: &str = "config.json";
+const SESSIONS_FILE: &str = "sessions.json";
+
+/// config.json in the FortniteLauncher folder of the platform configuration folder.
+pub fn default_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|folder| folder.join("FortniteLauncher").join(CONFIG_FILE))
+}
+
+fn read_object(path: &Path) -> Option<serde_json::Map<String, serde_json::Value>> {
+    let data_str = std::fs::read_to_string(path).ok()?;
+
+    match serde_json::from_str(&data_str).ok()? {
+        serde_json::Value::Object(value) => Some(value),
+        _ => None,
+    }
+}
+
+/// Returns true if `path` is a config.json written by the versions which kept it in the working directory :
+/// a single device auth and no version. Other tools may have their own config.json there.
+fn is_legacy(path: &Path) -> bool {
+    read_object(path).is_some_and(|value| !value.contains_key("version") && value.contains_key("device_auth"))
+}
+
+/// Returns true if `path` is a session cache written by the launcher.
+fn is_session_cache(path: &Path) -> bool {
+    read_object(path).is_some_and(|value| value.len() == 1 && value.get("sessions").is_some_and(serde_json::Value::is_object))
+}
+
+/// Removes the device auth secrets of a configuration, at any version.
+fn strip_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(values) => {
+            values.remove("secret");
+            values.values_mut().for_each(strip_secrets);
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(strip_secrets),
+        _ => {}
+    }
+}
+
+fn backup_data(mut value: serde_json::Value) -> Result<String, LauncherError> {
+    strip_secrets(&mut value);
+
+    serde_json::to_string_pretty(&value).map_err(|err| LauncherError::config("Failed to serialize the backup of the configuration", err))
+}
+
+/// Rewrites the backup of a configuration without its secrets, once they have been moved to another store.
+fn scrub_backup(path: &Path) -> Result<(), LauncherError> {
+    let data_str = std::fs::read_to_string(path)
+        .map_err(|err| LauncherError::config(format!("Failed to read {}", path.display()), err))?;
+
+    match serde_json::from_str(&data_str) {
+        Ok(value) => write_file(path, &backup_data(value)?)?,
+        //not a configuration we can clean, but it may still hold secrets
+        Err(_) => std::fs::remove_file(path)
+            .map_err(|err| LauncherError::config(format!("Failed to delete {}", path.display()), err))?,
+    }
+
+    tracing::info!(path = %path.display(), "Removed the secrets from the backup of the configuration");
+
+    Ok(())
+}
+
+pub(crate) fn write_file(path: &Path, data: &str) -> Result<(), LauncherError> {
+    if let Some(folder) = path.parent().filter(|folder| !folder.as_os_str().is_empty()) {
+        std::fs::create_dir_all(folder)
+            .map_err(|err| LauncherError::config(format!("Failed to create {}", folder.display()), err))?;
+    }
+
+    //written next to the target then renamed over it, so a crash never leaves a half written file
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+
+    let write = || -> std::io::Result<()> {
+        let mut file = File::create(&temporary)?;
+        file.write_all(data.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&temporary, path)
+    };
+
+    write().map_err(|err| {
+        let _ = std::fs::remove_file(&temporary);
+        LauncherError::config(format!("Failed to write {}", path.display()), err)
+    })
+}
+
+/// How long `ConfigLock::acquire` waits for another instance to release the configuration.
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+const LOCK_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Advisory lock of `config.json.lock`, held by a launcher from the moment it reads its configuration
+/// until it is dropped, so two instances never overwrite each other's credentials.
+/// It also covers sessions.json, which sits next to the configuration.
+pub struct ConfigLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl ConfigLock {
+    /// Locks the configuration at `path`. If another instance holds it, waits up to `LOCK_TIMEOUT` for it
+    /// before failing. The wait blocks the thread, `Launcher::open` runs it outside of the async workers.
+    pub fn acquire(path: &Path) -> Result<Self, LauncherError> {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        if let Some(folder) = lock_path.parent().filter(|folder| !folder.as_os_str().is_empty()) {
+            std::fs::create_dir_all(folder)
+                .map_err(|err| LauncherError::config(format!("Failed to create {}", folder.display()), err))?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .map_err(|err| LauncherError::config(format!("Failed to open {}", lock_path.display()), err))?;
+
+        let started = Instant::now();
+        let mut warned = false;
+
+        while let Err(err) = file.try_lock_exclusive() {
+            if err.kind() != fs2::lock_contended_error().kind() {
+                return Err(LauncherError::config(format!("Failed to lock {}", lock_path.display()), err));
+            }
+
+            //a launch or a device code login keeps the lock for minutes, failing beats hanging without a word
+            if started.elapsed() >= LOCK_TIMEOUT {
+                return Err(LauncherError::Config {
+                    message: format!(
+                        "Another launcher is running with {}, wait for it to exit or use another --config",
+                        path.display()
+                    ),
+                    source: None,
+                });
+            }
+
+            if !warned {
+                tracing::warn!(path = %lock_path.display(), "The configuration is used by another launcher, waiting for it");
+                warned = true;
+            }
+            std::thread::sleep(LOCK_RETRY_DELAY);
+        }
+
+        tracing::debug!(path = %lock_path.display(), "Locked the configuration");
+
+        Ok(Self { file, path: lock_path })
+    }
+}
+
+impl Drop for ConfigLock {
+    fn drop(&mut self) {
+        //the file is kept, removing it could let another instance lock a file which is about to disappear
+        let _ = self.file.unlock();
+        tracing::debug!(path = %self.path.display(), "Unlocked the configuration");
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
 pub struct DeviceAuth {
     #[serde(rename = "accountId")]
     pub account_id: String,
     #[serde(rename = "deviceId")]
     pub device_id: String,
+    /// Loaded from the secret store when `secret_ref` is set, and never written to the configuration then.
+    #[serde(default)]
     pub secret: String,
+    /// Where the secret is stored, see `SecretRef`.
+    #[serde(rename = "secretRef", default, skip_serializing_if = "Option::is_none")]
+    pub secret_ref: Option<String>,
+}
+
+impl HasSecrets for DeviceAuth {
+    fn secrets(&self) -> Vec<&str> {
+        vec![&self.secret]
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct AccountProfile {
+    pub name: String,
+    pub display_name: String,
+    pub device_auth: DeviceAuth,
+    pub last_used: Option<DateTime<Utc>>,
+    /// Options sent to caldera when launching with this account.
+    #[serde(default)]
+    pub caldera: CalderaOptions,
+    /// Changes made to the command line of the game when launching with this account.
+    #[serde(default)]
+    pub launch: LaunchOptions,
+}
+
+impl AccountProfile {
+    /// When the account was last used, as shown to the user.
+    pub fn last_used_label(&self) -> String {
+        match self.last_used {
+            Some(date) => date.format("%Y-%m-%d %H:%M").to_string(),
+            None => String::from("never"),
+        }
+    }
+}
+
+/// Names of the clients used at each step of the login, looked up in the client registry.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct LoginChain {
+    pub device_code_client: String,
+    pub onboarding_client: String,
+    pub launch_client: String,
+}
+
+impl Default for LoginChain {
+    fn default() -> Self {
+        Self {
+            device_code_client: FORTNITE_NEW_SWITCH_GAME_CLIENT.to_string(),
+            onboarding_client: FORTNITE_IOS_GAME_CLIENT.to_string(),
+            launch_client: LAUNCHER_APP_CLIENT_2.to_string(),
+        }
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
-pub(crate) struct Configuration {
-    pub device_auth: Option<DeviceAuth>,
-    pub fortnite_path: Option<String>
+pub struct Configuration {
+    /// Format of the file, older ones are migrated when the configuration is read.
+    #[serde(default)]
+    pub version: u64,
+    #[serde(default)]
+    pub accounts: Vec<AccountProfile>,
+    #[serde(default)]
+    pub default_account: Option<String>,
+    #[serde(default)]
+    pub fortnite_path: Option<String>,
+    /// Clients added to the built-in ones, or replacing them when they have the same name.
+    #[serde(default)]
+    pub clients: HashMap<String, Client>,
+    #[serde(default)]
+    pub login_chain: LoginChain,
+    #[serde(default)]
+    pub endpoints: EpicEndpoints,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// Anti cheat binaries added to the built-in ones, by provider name.
+    #[serde(default)]
+    pub anti_cheats: HashMap<String, AntiCheatBinary>,
+    /// Where the device auth secrets are stored. Secrets found elsewhere are moved there when the configuration is read.
+    #[serde(default)]
+    pub secret_store: SecretBackend,
+    /// File the configuration has been read from, and is written to.
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    lock: Option<Arc<ConfigLock>>,
+    #[serde(skip)]
+    stores: SecretStores,
+    /// Secrets stored by the configuration as last read or written, the others are deleted once it is written.
+    #[serde(skip)]
+    references: Vec<SecretRef>,
+    /// Content of the file as last read or written, to only write real changes.
+    #[serde(skip)]
+    saved: Option<String>,
 }
 
 impl Configuration {
-    pub fn read() -> Result<Self, Box<dyn std::error::Error>> {
-        let path_buf = std::path::PathBuf::from("config.json");
-        if path_buf.exists() {
-            let data_str = std::fs::read_to_string(path_buf)?;
-            let data: Configuration = serde_json::from_str(&data_str)?;
+    /// Path of the configuration : `explicit` if given, then the `FNL_CONFIG` environment variable,
+    /// then the platform configuration folder, or the working directory if the platform has none.
+    pub fn locate(explicit: Option<&Path>) -> PathBuf {
+        explicit
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os(CONFIG_ENV).map(PathBuf::from))
+            .or_else(default_config_path)
+            .unwrap_or_else(|| PathBuf::from(CONFIG_FILE))
+    }
+
+    /// Reads the configuration found by `locate`. When the platform folder is used and has no configuration yet,
+    /// the config.json written in the working directory by older versions is moved there.
+    pub fn open(explicit: Option<&Path>) -> Result<Self, LauncherError> {
+        let path = Self::locate(explicit);
+        let legacy = PathBuf::from(CONFIG_FILE);
+
+        if Some(&path) == default_config_path().as_ref() && !path.exists() && is_legacy(&legacy) {
+            let lock = ConfigLock::acquire(&path)?;
+            //the renamed file is the backup, none is written next to it
+            let mut data = Self::read_locked(&legacy, lock, false)?;
+            data.path = path;
+            data.stores = SecretStores::new(&data.path);
+            data.saved = None;
+            data.flush()?;
+
+            let mut migrated = legacy.as_os_str().to_owned();
+            migrated.push(".migrated");
+            let migrated = PathBuf::from(migrated);
+            std::fs::rename(&legacy, &migrated)
+                .map_err(|err| LauncherError::config(format!("Failed to rename {}", legacy.display()), err))?;
+            if data.secret_store != SecretBackend::Plaintext {
+                scrub_backup(&migrated)?;
+            }
+
+            //the cached sessions of older versions hold plaintext tokens
+            let sessions = PathBuf::from(SESSIONS_FILE);
+            if is_session_cache(&sessions) {
+                std::fs::remove_file(&sessions)
+                    .map_err(|err| LauncherError::config(format!("Failed to delete {}", sessions.display()), err))?;
+            }
+
+            tracing::info!(from = %legacy.display(), to = %data.path.display(), "Moved the configuration");
+
+            return Ok(data);
+        }
+
+        Self::read(&path)
+    }
+
+    /// Reads the configuration of `path`, migrating it to the current version if needed,
+    /// or creates a default one if there is none. The configuration stays locked until it is dropped.
+    pub fn read(path: &Path) -> Result<Self, LauncherError> {
+        let lock = ConfigLock::acquire(path)?;
+
+        Self::read_locked(path, lock, true)
+    }
+
+    fn read_locked(path: &Path, lock: ConfigLock, backup: bool) -> Result<Self, LauncherError> {
+        if path.exists() {
+            let data_str = std::fs::read_to_string(path)
+                .map_err(|err| LauncherError::config(format!("Failed to read {}", path.display()), err))?;
+            let mut value: serde_json::Value = serde_json::from_str(&data_str)
+                .map_err(|err| LauncherError::config(format!("Failed to parse {}", path.display()), err))?;
+
+            let original = value.clone();
+            let migrated = migrations::migrate(&mut value)?;
+
+            let mut data: Configuration = serde_json::from_value(value)
+                .map_err(|err| LauncherError::config(format!("Failed to parse {}", path.display()), err))?;
+
+            let backup_path = path.with_extension("json.bak");
+            if migrated && backup {
+                //keep the original around in case the migration went wrong, without the secrets unless they are kept in plaintext
+                let backup_str = match data.secret_store {
+                    SecretBackend::Plaintext => data_str,
+                    _ => backup_data(original)?,
+                };
+                write_file(&backup_path, &backup_str)?;
+            }
+            data.path = path.to_path_buf();
+            data.lock = Some(Arc::new(lock));
+            data.stores = SecretStores::new(path);
+
+            let moved = data.load_secrets()?;
+            for account in &data.accounts {
+                redact::register_all(&account.device_auth);
+            }
+
+            if migrated || moved {
+                data.flush()?;
+            } else {
+                data.saved = Some(data.serialize()?);
+            }
+
+            if moved && !migrated && data.secret_store != SecretBackend::Plaintext && backup_path.exists() {
+                scrub_backup(&backup_path)?;
+            }
 
             Ok(data)
         } else {
             //create default config
-            let data = Self::default();
+            let mut data = Self::default();
+            data.path = path.to_path_buf();
+            data.lock = Some(Arc::new(lock));
+            data.stores = SecretStores::new(path);
             data.flush()?;
 
             Ok(data)
         }
     }
 
-    pub fn flush(&self) -> Result<(), Box<dyn std::error::Error>> {
-        std::fs::write("config.json", serde_json::to_string_pretty(&self)?)?;
-        if cfg!(debug_assertions){
-            println!("Flushed configuration successfully !");
+    /// Loads the secrets of the accounts from their stores,
+    /// returns true if some of them are not in the chosen store and have to be moved.
+    fn load_secrets(&mut self) -> Result<bool, LauncherError> {
+        let mut moved = false;
+
+        for account in &mut self.accounts {
+            let device_auth = &mut account.device_auth;
+
+            match &device_auth.secret_ref {
+                Some(reference) => {
+                    let reference = SecretRef::parse(reference)?;
+                    device_auth.secret = self.stores.read(&reference)?;
+                    moved |= reference.backend != self.secret_store;
+                    self.references.push(reference);
+                }
+                None => moved |= self.secret_store != SecretBackend::Plaintext,
+            }
+        }
+
+        if moved {
+            tracing::info!(store = %self.secret_store, "Moving the secrets of the configuration");
         }
+
+        Ok(moved)
+    }
+
+    /// Writes the secrets of the accounts to the chosen store and points their device auths to them,
+    /// returns the secrets which are not referenced anymore.
+    fn store_secrets(&mut self) -> Result<Vec<SecretRef>, LauncherError> {
+        let store = self.stores.get(self.secret_store)?;
+        let mut references = Vec::new();
+
+        for account in &mut self.accounts {
+            let device_auth = &mut account.device_auth;
+
+            device_auth.secret_ref = match &store {
+                Some(store) => {
+                    //the device id keeps apart the device auths of the same account in other configurations
+                    let key = format!("{}:{}", device_auth.account_id, device_auth.device_id);
+                    let reference = SecretRef::new(self.secret_store, &key);
+                    if store.get(&reference.key)?.as_deref() != Some(device_auth.secret.as_str()) {
+                        store.set(&reference.key, &device_auth.secret)?;
+                    }
+
+                    let reference_str = reference.to_string();
+                    references.push(reference);
+                    Some(reference_str)
+                }
+                None => None,
+            };
+        }
+
+        //removed accounts, or secrets moved to another store
+        let previous = std::mem::replace(&mut self.references, references);
+
+        Ok(previous.into_iter().filter(|reference| !self.references.contains(reference)).collect())
+    }
+
+    fn serialize(&self) -> Result<String, LauncherError> {
+        serde_json::to_string_pretty(&self).map_err(|err| LauncherError::config("Failed to serialize the configuration", err))
+    }
+
+    /// Content of config.json : the configuration without the secrets kept in a store.
+    fn serialize_file(&self) -> Result<String, LauncherError> {
+        let mut value = serde_json::to_value(self)
+            .map_err(|err| LauncherError::config("Failed to serialize the configuration", err))?;
+
+        if let Some(accounts) = value.get_mut("accounts").and_then(serde_json::Value::as_array_mut) {
+            for device_auth in accounts
+                .iter_mut()
+                .filter_map(|account| account.get_mut("device_auth").and_then(serde_json::Value::as_object_mut))
+            {
+                if device_auth.contains_key("secretRef") {
+                    device_auth.remove("secret");
+                }
+            }
+        }
+
+        serde_json::to_string_pretty(&value).map_err(|err| LauncherError::config("Failed to serialize the configuration", err))
+    }
+
+    /// Returns true if the configuration has changed since it was last read or written.
+    pub fn is_dirty(&self) -> bool {
+        self.serialize().map_or(true, |data| self.saved.as_ref() != Some(&data))
+    }
+
+    /// Writes the configuration if it has changed since it was last read or written,
+    /// storing the secrets first and deleting the unused ones once the file has been replaced.
+    pub fn flush(&mut self) -> Result<(), LauncherError> {
+        if !self.is_dirty() {
+            return Ok(());
+        }
+
+        let unused = self.store_secrets()?;
+        write_file(&self.path, &self.serialize_file()?)?;
+        self.saved = Some(self.serialize()?);
+        tracing::debug!(path = %self.path.display(), "Flushed configuration successfully");
+
+        for reference in unused {
+            self.stores.delete(&reference)?;
+        }
+
         Ok(())
     }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// sessions.json, next to the configuration.
+    pub fn sessions_path(&self) -> PathBuf {
+        self.path.with_file_name(SESSIONS_FILE)
+    }
+
+    /// Reads the session cache next to the configuration, encrypted with a key kept in its secret store.
+    pub fn session_cache(&self) -> Result<SessionCache, LauncherError> {
+        SessionCache::read(&self.sessions_path(), self.secret_store, self.stores.clone())
+    }
+
+    pub fn context(&self) -> EpicContext {
+        EpicContext::new(self.endpoints.clone(), self.retry.clone())
+    }
+
+    pub fn client_registry(&self) -> ClientRegistry {
+        ClientRegistry::with_overrides(&self.clients)
+    }
+
+    pub fn anti_cheat_registry(&self) -> AntiCheatRegistry {
+        AntiCheatRegistry::with_overrides(&self.anti_cheats)
+    }
+
+    pub fn login_clients(&self) -> Result<LoginClients, LauncherError> {
+        let registry = self.client_registry();
+
+        Ok(LoginClients {
+            device_code: registry.get(&self.login_chain.device_code_client)?.clone(),
+            onboarding: registry.get(&self.login_chain.onboarding_client)?.clone(),
+            launch: registry.get(&self.login_chain.launch_client)?.clone(),
+        })
+    }
+
+    /// Finds an account by its profile name, display name or account id.
+    pub fn find_account(&self, name: &str) -> Option<&AccountProfile> {
+        self.accounts.iter().find(|account| {
+            account.name.eq_ignore_ascii_case(name)
+                || account.display_name.eq_ignore_ascii_case(name)
+                || account.device_auth.account_id == name
+        })
+    }
+
+    pub fn find_account_by_id(&self, account_id: &str) -> Option<&AccountProfile> {
+        self.accounts
+            .iter()
+            .find(|account| account.device_auth.account_id == account_id)
+    }
+
+    pub fn get_default_account(&self) -> Option<&AccountProfile> {
+        self.default_account
+            .as_ref()
+            .and_then(|name| self.find_account(name))
+    }
+
+    /// Adds an account, replacing the existing profile of the same epic account if there is one
+    /// but keeping its caldera and launch options.
+    /// The first account added becomes the default one.
+    pub fn add_account(&mut self, name: &str, display_name: &str, device_auth: DeviceAuth) {
+        let profile = AccountProfile {
+            name: name.to_string(),
+            display_name: display_name.to_string(),
+            device_auth,
+            last_used: None,
+            caldera: CalderaOptions::default(),
+            launch: LaunchOptions::default(),
+        };
+
+        match self
+            .accounts
+            .iter_mut()
+            .find(|account| account.device_auth.account_id == profile.device_auth.account_id)
+        {
+            Some(account) => {
+                let caldera = std::mem::take(&mut account.caldera);
+                let launch = std::mem::take(&mut account.launch);
+                *account = AccountProfile { caldera, launch, ..profile };
+            }
+            None => self.accounts.push(profile),
+        }
+
+        if self.get_default_account().is_none() {
+            self.default_account = Some(name.to_string());
+        }
+    }
+
+    /// Removes an account, the default account pointer is moved to the first remaining account if needed.
+    pub fn remove_account(&mut self, name: &str) -> Option<AccountProfile> {
+        let account_id = self.find_account(name)?.device_auth.account_id.clone();
+        let index = self
+            .accounts
+            .iter()
+            .position(|account| account.device_auth.account_id == account_id)?;
+
+        let profile = self.accounts.remove(index);
+
+        if self.get_default_account().is_none() {
+            self.default_account = self.accounts.first().map(|account| account.name.clone());
+        }
+
+        Some(profile)
+    }
+
+    /// Replaces the device auth of an account, keeping the rest of its profile.
+    pub fn set_device_auth(&mut self, device_auth: DeviceAuth) {
+        if let Some(account) = self
+            .accounts
+            .iter_mut()
+            .find(|account| account.device_auth.account_id == device_auth.account_id)
+        {
+            account.device_auth = device_auth;
+        }
+    }
+
+    pub fn touch_account(&mut self, account_id: &str) {
+        if let Some(account) = self
+            .accounts
+            .iter_mut()
+            .find(|account| account.device_auth.account_id == account_id)
+        {
+            account.last_used = Some(Utc::now());
+        }
+    }
 }
 
 impl Drop for Configuration {
     fn drop(&mut self) {
-        let _ = self.flush();
+        //saving is up to the owner, which can report the error, see `Launcher::save`
+        if self.lock.is_some() && self.is_dirty() {
+            tracing::warn!(path = %self.path.display(), "The configuration has unsaved changes");
+        }
     }
 }
 
 impl Default for Configuration {
     fn default() -> Self {
-        Self { device_auth: None, fortnite_path: None }
+        Self {
+            version: migrations::CURRENT_VERSION,
+            accounts: Vec::new(),
+            default_account: None,
+            fortnite_path: None,
+            clients: HashMap::new(),
+            login_chain: LoginChain::default(),
+            endpoints: EpicEndpoints::default(),
+            retry: RetryPolicy::default(),
+            anti_cheats: HashMap::new(),
+            secret_store: SecretBackend::default(),
+            path: PathBuf::from(CONFIG_FILE),
+            lock: None,
+            stores: SecretStores::default(),
+            references: Vec::new(),
+            saved: None,
+        }
+    }
+}
+
+/// Last known `AccountDetails` of each account, keyed by account and client id, so a launch can skip the login chain.
+/// It is not locked by itself, read it while holding the lock of the configuration next to it.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Default)]
+pub struct SessionCache {
+    pub sessions: HashMap<String, AccountDetails>,
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    saved: Option<String>,
+    /// Store of the key the cache is encrypted with, it is written in plaintext for the plaintext store.
+    #[serde(skip)]
+    backend: SecretBackend,
+    #[serde(skip)]
+    stores: SecretStores,
+    #[serde(skip)]
+    key: Option<SecretRef>,
+}
+
+impl SessionCache {
+    /// Reads the cache at `path`, encrypted with a key kept in the store of `backend`.
+    /// A cache which can't be decrypted anymore is dropped, the sessions are created again on the next launch.
+    pub fn read(path: &Path, backend: SecretBackend, stores: SecretStores) -> Result<Self, LauncherError> {
+        let file = if path.exists() {
+            let data_str = std::fs::read_to_string(path)
+                .map_err(|err| LauncherError::config(format!("Failed to read {}", path.display()), err))?;
+
+            match stores.unseal(path, &data_str) {
+                Ok(Some((data_str, key))) => Some((data_str, Some(key))),
+                Ok(None) => Some((data_str, None)),
+                Err(err) => {
+                    tracing::warn!(path = %path.display(), error = %err, "Dropped the session cache");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut data = match &file {
+            Some((data_str, _)) => serde_json::from_str(data_str)
+                .map_err(|err| LauncherError::config(format!("Failed to parse {}", path.display()), err))?,
+            None => Self::default(),
+        };
+        data.path = path.to_path_buf();
+        data.backend = backend;
+        data.stores = stores;
+        data.key = file.and_then(|(_, key)| key);
+
+        for details in data.sessions.values() {
+            redact::register_all(details);
+        }
+
+        //an empty cache is only written once something is cached,
+        //a cache in plaintext or in another store is written again to the chosen store
+        let chosen = (backend != SecretBackend::Plaintext).then_some(backend);
+        if data.sessions.is_empty() || data.key.as_ref().map(|key| key.backend) == chosen {
+            data.saved = Some(data.serialize()?);
+        }
+
+        Ok(data)
+    }
+
+    fn serialize(&self) -> Result<String, LauncherError> {
+        serde_json::to_string_pretty(&self).map_err(|err| LauncherError::config("Failed to serialize the session cache", err))
+    }
+
+    /// Returns true if the cache has changed since it was last read or written.
+    pub fn is_dirty(&self) -> bool {
+        self.serialize().map_or(true, |data| self.saved.as_ref() != Some(&data))
+    }
+
+    /// Writes the cache if it has changed since it was last read or written.
+    pub fn flush(&mut self) -> Result<(), LauncherError> {
+        let data = self.serialize()?;
+        if self.saved.as_ref() == Some(&data) {
+            return Ok(());
+        }
+
+        let key = match &self.key {
+            Some(key) if key.backend == self.backend => Some(key.clone()),
+            _ => self.stores.create_key(self.backend, "sessions")?,
+        };
+        match &key {
+            Some(key) => write_file(&self.path, &self.stores.seal(key, &data)?)?,
+            None => write_file(&self.path, &data)?,
+        }
+        self.saved = Some(data);
+
+        //the cache moved to another store
+        if let Some(previous) = std::mem::replace(&mut self.key, key).filter(|previous| Some(previous) != self.key.as_ref()) {
+            self.stores.delete(&previous)?;
+        }
+
+        tracing::debug!("Flushed session cache successfully");
+        Ok(())
+    }
+
+    fn key(account_id: &str, client_id: &str) -> String {
+        format!("{}:{}", account_id, client_id)
+    }
+
+    /// Returns the cached details if they can still be used, either directly or by refreshing them.
+    pub fn get(&self, account_id: &str, client_id: &str) -> Option<AccountDetails> {
+        self.sessions
+            .get(&Self::key(account_id, client_id))
+            .filter(|details| !details.get_access_token().is_expired() || !details.get_refresh_token().is_expired())
+            .cloned()
+    }
+
+    pub fn insert(&mut self, details: &AccountDetails) {
+        self.sessions.insert(Self::key(details.get_account_id(), details.get_client_id()), details.clone());
+    }
+
+    pub fn invalidate(&mut self, account_id: &str, client_id: &str) {
+        self.sessions.remove(&Self::key(account_id, client_id));
+    }
+
+    /// Forgets every cached session of an account, whatever the client.
+    pub fn invalidate_account(&mut self, account_id: &str) {
+        self.sessions.retain(|_, details| details.get_account_id() != account_id);
+    }
+}
+
+impl Drop for SessionCache {
+    fn drop(&mut self) {
+        if self.is_dirty() {
+            tracing::warn!(path = %self.path.display(), "The session cache has unsaved changes");
+        }
     }
 }
@@ -0,0 +1,113 @@
+use std::{future::Future, time::Duration};
+
+use futures::{stream, Stream, StreamExt};
+use tokio::time::Instant;
+
+use crate::{
+    epic::{self, AccountDetails, Client, DeviceCode},
+    error::LauncherError,
+    rest::{EpicContext, EpicError},
+};
+
+/// Seconds added to the polling interval each time epic answers `slow_down`, as defined by RFC 8628.
+const SLOW_DOWN_INCREMENT_SECONDS: u64 = 5;
+
+pub enum PollState {
+    /// The user has not entered the code yet.
+    Pending,
+    /// Epic asked to poll less often, contains the new interval.
+    SlowDown(Duration),
+    Authorized(AccountDetails),
+    Expired,
+    Failed(LauncherError),
+}
+
+struct Poller {
+    context: EpicContext,
+    device_code: DeviceCode,
+    client: Client,
+    interval: Duration,
+    deadline: Instant,
+    done: bool,
+}
+
+/// Polls the token endpoint until the device code is authorized, expires, or an unexpected error happens.
+/// The stream ends right after yielding `Authorized`, `Expired` or `Failed`, dropping it cancels the polling.
+pub fn poll(context: EpicContext, device_code: DeviceCode, client: Client) -> impl Stream<Item = PollState> {
+    let poller = Poller {
+        context,
+        interval: Duration::from_secs(device_code.interval.max(1) as u64),
+        deadline: Instant::now() + Duration::from_secs(device_code.expires_in.max(0) as u64),
+        device_code,
+        client,
+        done: false,
+    };
+
+    stream::unfold(poller, |mut poller| async move {
+        if poller.done {
+            return None;
+        }
+
+        let now = Instant::now();
+        if now >= poller.deadline {
+            poller.done = true;
+            return Some((PollState::Expired, poller));
+        }
+
+        tokio::time::sleep_until((now + poller.interval).min(poller.deadline)).await;
+
+        let state = match epic::login_with_device_code(&poller.context, &poller.device_code, &poller.client).await {
+            Ok(details) => {
+                poller.done = true;
+                PollState::Authorized(details)
+            }
+            Err(err) => match &err {
+                LauncherError::Epic(EpicError::AuthorizationPending(_)) => PollState::Pending,
+                LauncherError::Epic(EpicError::SlowDown(_)) => {
+                    poller.interval += Duration::from_secs(SLOW_DOWN_INCREMENT_SECONDS);
+                    PollState::SlowDown(poller.interval)
+                }
+                LauncherError::Epic(EpicError::DeviceCodeExpired(_) | EpicError::NotFound(_)) => {
+                    poller.done = true;
+                    PollState::Expired
+                }
+                _ => {
+                    poller.done = true;
+                    PollState::Failed(err)
+                }
+            },
+        };
+
+        Some((state, poller))
+    })
+}
+
+/// Waits for the user to enter the device code, gives up when `cancel` completes.
+pub async fn wait_for_authorization<F>(
+    context: EpicContext,
+    device_code: DeviceCode,
+    client: Client,
+    cancel: F,
+) -> Result<AccountDetails, LauncherError>
+where
+    F: Future,
+{
+    let states = poll(context, device_code, client);
+    tokio::pin!(states);
+    tokio::pin!(cancel);
+
+    loop {
+        tokio::select! {
+            _ = &mut cancel => return Err(LauncherError::Cancelled),
+            state = states.next() => match state {
+                Some(PollState::Pending) => (),
+                Some(PollState::SlowDown(interval)) => {
+                    tracing::info!(interval_seconds = interval.as_secs(), "Epic asked to slow down the device code polling");
+                }
+                Some(PollState::Authorized(details)) => return Ok(details),
+                Some(PollState::Expired) | None => return Err(EpicError::Expired.into()),
+                Some(PollState::Failed(err)) => return Err(err),
+            }
+        }
+    }
+}
@@ -1,8 +1,10 @@
 use std::collections::HashMap;
 
-use crate::{rest::{self, handle_epic_response, EpicError}, config::DeviceAuth};
+use chrono::{DateTime, Duration, Utc};
 
-#[derive(Debug)]
+use crate::{rest::{EpicContext, EpicError}, config::DeviceAuth, error::LauncherError, jwt::Jwt, redact::{self, HasSecrets}};
+
+#[derive(Debug, Clone, Copy)]
 pub enum AuthentificationType {
     AuthorizationCode = 1 ,
     DeviceCode = 2
@@ -13,16 +15,134 @@ impl std::str::FromStr for AuthentificationType {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.trim() {
-            "1" => Ok(AuthentificationType::AuthorizationCode),
-            "2" => Ok(AuthentificationType::DeviceCode),
+            "1" | "authorization-code" => Ok(AuthentificationType::AuthorizationCode),
+            "2" | "device-code" => Ok(AuthentificationType::DeviceCode),
             _ => Err("Invalid authentication type"),
         }
     }
 }
 
-pub struct Client<'a> {
-    pub id: &'a str,
-    pub secret: &'a str,
+/// Extracts an authorization code from what the user pasted : the JSON body of the redirect page,
+/// the redirect url, a query string or the bare code.
+pub fn parse_authorization_code(input: &str) -> Result<String, LauncherError> {
+    let invalid = |message: String| LauncherError::Input { message, source: None };
+    let input = input.trim().trim_matches('"');
+
+    let code = if input.starts_with('{') {
+        let value: serde_json::Value = serde_json::from_str(input)
+            .map_err(|err| LauncherError::input("Failed to parse the redirect JSON", err))?;
+
+        if let Some(code) = ["authorizationCode", "code"]
+            .iter()
+            .find_map(|key| value.get(key).and_then(|code| code.as_str()))
+        {
+            code.to_string()
+        } else if let Some(redirect_url) = value.get("redirectUrl").and_then(|url| url.as_str()) {
+            return parse_authorization_code(redirect_url);
+        } else {
+            return Err(invalid(String::from("The redirect JSON does not contain any authorization code, are you logged in ?")));
+        }
+    } else if let Ok(url) = reqwest::Url::parse(input) {
+        url.query_pairs()
+            .find(|(key, _)| key == "code")
+            .map(|(_, code)| code.into_owned())
+            .ok_or_else(|| invalid(String::from("The url does not contain any authorization code")))?
+    } else if input.contains("code=") {
+        //a url pasted without its scheme, or a bare query string
+        input
+            .rsplit_once('?')
+            .map_or(input, |(_, query)| query)
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("code="))
+            .ok_or_else(|| invalid(String::from("The query string does not contain any authorization code")))?
+            .to_string()
+    } else {
+        input.to_string()
+    };
+
+    if code.len() != 32 || !code.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid(format!("{} is not a valid authorization code, it should be 32 hexadecimal characters", code)));
+    }
+
+    Ok(code)
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+pub struct Client {
+    pub id: String,
+    pub secret: String,
+}
+
+impl Client {
+    pub fn new(id: &str, secret: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            secret: secret.to_string(),
+        }
+    }
+}
+
+pub const FORTNITE_NEW_SWITCH_GAME_CLIENT: &str = "fortniteNewSwitchGameClient";
+pub const LAUNCHER_APP_CLIENT_2: &str = "launcherAppClient2";
+pub const FORTNITE_IOS_GAME_CLIENT: &str = "fortniteIOSGameClient";
+
+/// Clients known by the launcher, by name. The built-in ones can be overridden from the configuration
+/// when epic rotates them.
+#[derive(Clone, Debug)]
+pub struct ClientRegistry {
+    clients: HashMap<String, Client>,
+}
+
+impl Default for ClientRegistry {
+    fn default() -> Self {
+        let mut clients = HashMap::new();
+        clients.insert(
+            FORTNITE_NEW_SWITCH_GAME_CLIENT.to_string(),
+            Client::new("98f7e42c2e3a4f86a74eb43fbb41ed39", "0a2449a2-001a-451e-afec-3e812901c4d7"),
+        );
+        clients.insert(
+            LAUNCHER_APP_CLIENT_2.to_string(),
+            Client::new("34a02cf8f4414e29b15921876da36f9a", "daafbccc737745039dffe53d94fc76cf"),
+        );
+        clients.insert(
+            FORTNITE_IOS_GAME_CLIENT.to_string(),
+            Client::new("3446cd72694c4a4485d81b77adbb2141", "9209d4a5e25a457fb9b07489d313b41a"),
+        );
+
+        Self { clients }
+    }
+}
+
+impl ClientRegistry {
+    /// Built-in clients, extended or overridden by `overrides`.
+    pub fn with_overrides(overrides: &HashMap<String, Client>) -> Self {
+        let mut registry = Self::default();
+        registry.clients.extend(overrides.iter().map(|(name, client)| (name.clone(), client.clone())));
+
+        registry
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Client, LauncherError> {
+        self.clients.get(name).ok_or_else(|| LauncherError::Config {
+            message: format!("Unknown client {}, add it to the clients of config.json", name),
+            source: None,
+        })
+    }
+
+    pub fn find_by_id(&self, id: &str) -> Option<&Client> {
+        self.clients.values().find(|client| client.id == id)
+    }
+}
+
+/// Clients used at each step of the login chain.
+#[derive(Clone, Debug)]
+pub struct LoginClients {
+    /// Requests device codes, must be allowed to use the device_code grant.
+    pub device_code: Client,
+    /// Creates and uses the device auths of the accounts.
+    pub onboarding: Client,
+    /// Owns the sessions used to launch the game.
+    pub launch: Client,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq)]
@@ -50,29 +170,167 @@ impl Default for AntiCheatProviderIn {
     }
 }
 
+/// Options sent to caldera when requesting the anti cheat provider, the unset ones keep their default value.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct CalderaOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub epic_app: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test_mode: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nvidia: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub luna: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salmon: Option<bool>,
+}
+
+impl CalderaOptions {
+    /// Returns these options, overridden by the ones set in `other`.
+    pub fn merge(&self, other: &CalderaOptions) -> CalderaOptions {
+        CalderaOptions {
+            epic_app: other.epic_app.clone().or_else(|| self.epic_app.clone()),
+            test_mode: other.test_mode.or(self.test_mode),
+            nvidia: other.nvidia.or(self.nvidia),
+            luna: other.luna.or(self.luna),
+            salmon: other.salmon.or(self.salmon),
+        }
+    }
+
+    pub fn apply(&self, body: &mut AntiCheatProviderIn) {
+        if let Some(epic_app) = &self.epic_app {
+            body.epic_app = epic_app.clone();
+        }
+        body.test_mode = self.test_mode.unwrap_or(body.test_mode);
+        body.nvidia = self.nvidia.unwrap_or(body.nvidia);
+        body.luna = self.luna.unwrap_or(body.luna);
+        body.salmon = self.salmon.unwrap_or(body.salmon);
+    }
+}
+
+pub const EASY_ANTI_CHEAT: &str = "EasyAntiCheat";
+pub const EASY_ANTI_CHEAT_EOS: &str = "EasyAntiCheatEOS";
+pub const BATTL_EYE: &str = "BattlEye";
+
+/// Anti cheat chosen by caldera. Providers the launcher doesn't know are kept by name,
+/// so they can be described in the configuration.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(from = "String", into = "String")]
+pub enum AntiCheatKind {
+    EasyAntiCheat,
+    EasyAntiCheatEOS,
+    BattlEye,
+    Unknown(String),
+}
+
+impl AntiCheatKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AntiCheatKind::EasyAntiCheat => EASY_ANTI_CHEAT,
+            AntiCheatKind::EasyAntiCheatEOS => EASY_ANTI_CHEAT_EOS,
+            AntiCheatKind::BattlEye => BATTL_EYE,
+            AntiCheatKind::Unknown(name) => name,
+        }
+    }
+}
+
+impl From<String> for AntiCheatKind {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            EASY_ANTI_CHEAT => AntiCheatKind::EasyAntiCheat,
+            EASY_ANTI_CHEAT_EOS => AntiCheatKind::EasyAntiCheatEOS,
+            BATTL_EYE => AntiCheatKind::BattlEye,
+            _ => AntiCheatKind::Unknown(name),
+        }
+    }
+}
+
+impl From<AntiCheatKind> for String {
+    fn from(kind: AntiCheatKind) -> Self {
+        kind.as_str().to_string()
+    }
+}
+
+impl std::fmt::Display for AntiCheatKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct AntiCheatProvider {
-    pub provider:String,
+    pub provider:AntiCheatKind,
     pub jwt:String
 }
 
-pub const FORTNITE_NEW_SWITCH_GAME_CLIENT: Client<'static> = Client {
-    id: "98f7e42c2e3a4f86a74eb43fbb41ed39",
-    secret: "0a2449a2-001a-451e-afec-3e812901c4d7",
-};
+impl AntiCheatProvider {
+    pub fn decode_jwt(&self) -> Result<Jwt, LauncherError> {
+        Jwt::decode(&self.jwt)
+    }
+}
+
+const TOKEN:&str = "/account/api/oauth/token";
+
+/// Parses a response holding credentials and registers them, so they never reach the logs.
+async fn secret_json<T>(response: reqwest::Response) -> Result<T, LauncherError>
+where T: HasSecrets + serde::de::DeserializeOwned
+{
+    let value = response.json::<T>().await?;
+    redact::register_all(&value);
+
+    Ok(value)
+}
+
+/// Sessions killed by `kill_sessions`, relative to the session used to make the request.
+#[derive(Debug, Clone, Copy)]
+pub enum KillType {
+    All,
+    Others,
+    AllAccountClient,
+    OthersAccountClient,
+    OthersAccountClientService,
+}
 
-#[allow(dead_code)]
-pub const LAUNCHER_APP_CLIENT_2: Client<'static> = Client {
-    id: "34a02cf8f4414e29b15921876da36f9a",
-    secret: "daafbccc737745039dffe53d94fc76cf",
-};
+impl KillType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KillType::All => "ALL",
+            KillType::Others => "OTHERS",
+            KillType::AllAccountClient => "ALL_ACCOUNT_CLIENT",
+            KillType::OthersAccountClient => "OTHERS_ACCOUNT_CLIENT",
+            KillType::OthersAccountClientService => "OTHERS_ACCOUNT_CLIENT_SERVICE",
+        }
+    }
+}
 
-pub const FORTNITE_IOS_GAME_CLIENT: Client<'static> = Client {
-    id: "3446cd72694c4a4485d81b77adbb2141",
-    secret: "9209d4a5e25a457fb9b07489d313b41a",
-};
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct TokenPermission {
+    pub resource: String,
+    pub action: i32,
+}
 
-const TOKEN:&'static str = "https://account-public-service-prod.ol.epicgames.com/account/api/oauth/token";
+/// Token as seen by epic, returned by `verify_token`.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct TokenVerification {
+    pub token: String,
+    pub session_id: String,
+    pub token_type: String,
+    pub client_id: String,
+    pub internal_client: bool,
+    pub client_service: String,
+    pub account_id: Option<String>,
+    pub expires_in: i64,
+    pub expires_at: String,
+    pub auth_method: String,
+    pub display_name: Option<String>,
+    pub app: Option<String>,
+    pub in_app_id: Option<String>,
+    pub device_id: Option<String>,
+    #[serde(default)]
+    pub scope: Vec<String>,
+    #[serde(default)]
+    pub perms: Vec<TokenPermission>,
+}
 
 #[derive(serde::Deserialize, Clone)]
 pub struct ExchangeCode {
@@ -118,12 +376,59 @@ pub struct AccountDetails {
     pub in_app_id: String
 }
 
+#[derive(Default, Debug, Clone, serde::Deserialize)]
+pub struct DeviceAuthAccess {
+    pub location: Option<String>,
+    #[serde(rename = "ipAddress")]
+    pub ip_address: Option<String>,
+    #[serde(rename = "dateTime")]
+    pub date_time: Option<String>,
+}
+
+/// Device auth as listed by epic, the secret is only returned once when it is created.
+#[derive(Default, Debug, Clone, serde::Deserialize)]
+pub struct DeviceAuthInfo {
+    #[serde(rename = "deviceId")]
+    pub device_id: String,
+    #[serde(rename = "accountId")]
+    pub account_id: String,
+    #[serde(rename = "userAgent")]
+    pub user_agent: Option<String>,
+    pub created: Option<DeviceAuthAccess>,
+    #[serde(rename = "lastAccess")]
+    pub last_access: Option<DeviceAuthAccess>,
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct Token {
     pub token:String,
     pub expires_at:String
 }
 
+impl Token {
+    pub fn expiration(&self) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc3339(&self.expires_at)
+            .ok()
+            .map(|date| date.with_timezone(&Utc))
+    }
+
+    /// Returns true if the token expires in less than `margin`, or if its expiration date cannot be parsed.
+    pub fn expires_within(&self, margin: Duration) -> bool {
+        match self.expiration() {
+            Some(expiration) => expiration - margin <= Utc::now(),
+            None => true
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_within(Duration::zero())
+    }
+
+    pub fn decode(&self) -> Result<Jwt, LauncherError> {
+        Jwt::decode(&self.token)
+    }
+}
+
 pub trait HasClient {
     fn get_client_id(&self) -> &str;
 }
@@ -142,6 +447,36 @@ pub trait HasIdentity {
     fn get_account_id(&self) -> &str;
 }
 
+impl HasSecrets for BasicDetails {
+    fn secrets(&self) -> Vec<&str> {
+        vec![&self.access_token]
+    }
+}
+
+impl HasSecrets for AccountDetails {
+    fn secrets(&self) -> Vec<&str> {
+        vec![&self.access_token, &self.refresh_token]
+    }
+}
+
+impl HasSecrets for ExchangeCode {
+    fn secrets(&self) -> Vec<&str> {
+        vec![&self.code]
+    }
+}
+
+impl HasSecrets for DeviceCode {
+    fn secrets(&self) -> Vec<&str> {
+        vec![&self.device_code]
+    }
+}
+
+impl HasSecrets for AntiCheatProvider {
+    fn secrets(&self) -> Vec<&str> {
+        vec![&self.jwt]
+    }
+}
+
 impl HasToken for BasicDetails {
     fn get_access_token(&self) -> Token {
         Token{
@@ -191,46 +526,45 @@ impl HasIdentity for AccountDetails {
     }
 }
 
-pub async fn client_credentials<'a>(
-    client: &Client<'a>,
-) -> Result<BasicDetails, Box<dyn std::error::Error>> {
+pub async fn client_credentials(
+    context: &EpicContext,
+    client: &Client,
+) -> Result<BasicDetails, LauncherError> {
     let mut body = HashMap::new();
     body.insert("grant_type", "client_credentials");
 
-    let response = handle_epic_response(
-        rest::CLIENT
-            .post(TOKEN)
+    let response = context.send_once(
+        context.http
+            .post(context.endpoints.account(TOKEN))
             .form(&body)
-            .basic_auth(client.id, Some(client.secret))
-            .send()
-            .await?
-    )
-    .await?;
+            .basic_auth(&client.id, Some(&client.secret))
+    ).await?;
 
-    let details: BasicDetails = response.json().await?;
+    let details: BasicDetails = secret_json(response).await?;
 
     Ok(details)
 }
 
 pub async fn create_device_code(
+    context: &EpicContext,
     client_credentials: &BasicDetails,
-) -> Result<DeviceCode, Box<dyn std::error::Error>> {
+) -> Result<DeviceCode, LauncherError> {
 
     let mut body = HashMap::new();
     body.insert("prompt", "login");
 
-    let response = handle_epic_response(
-        rest::CLIENT.
-        post("https://account-public-service-prod.ol.epicgames.com/account/api/oauth/deviceAuthorization")
+    let response = context.send_once(
+        context.http
+        .post(context.endpoints.account("/account/api/oauth/deviceAuthorization"))
         .form(&body)
-        .bearer_auth(&client_credentials.access_token).send().await?
+        .bearer_auth(&client_credentials.access_token)
     ).await?;
 
-    Ok(response.json::<DeviceCode>().await?)
+    secret_json::<DeviceCode>(response).await
 }
 
 
-pub async fn login_with_device_code<'a>(device_code:&DeviceCode, client: &Client<'a>) -> Result<AccountDetails, Box<dyn std::error::Error>>
+pub async fn login_with_device_code(context:&EpicContext, device_code:&DeviceCode, client: &Client) -> Result<AccountDetails, LauncherError>
 {
     let mut body = HashMap::new();
     body.insert("grant_type", "device_code");
@@ -240,86 +574,102 @@ pub async fn login_with_device_code<'a>(device_code:&DeviceCode, client: &Client
         return Err(EpicError::ClientMismatch.into());
     }
 
-    let response = handle_epic_response(
-        rest::CLIENT
-            .post(TOKEN)
+    let response = context.send_once(
+        context.http
+            .post(context.endpoints.account(TOKEN))
             .form(&body)
-            .basic_auth(client.id, Some(client.secret))
-            .send()
-            .await?
-    )
-    .await?;
+            .basic_auth(&client.id, Some(&client.secret))
+    ).await?;
 
-    Ok(response.json::<AccountDetails>().await?)
+    secret_json::<AccountDetails>(response).await
 }
 
-pub async fn exchange_code<'a, T:HasToken>(details:&T) -> Result<ExchangeCode, Box<dyn std::error::Error>>
+pub async fn exchange_code<T:HasToken>(context:&EpicContext, details:&T) -> Result<ExchangeCode, LauncherError>
 {
-    let response = handle_epic_response(
-        rest::CLIENT
-        .get("https://account-public-service-prod.ol.epicgames.com/account/api/oauth/exchange")
+    let response = context.send_once(
+        context.http
+        .get(context.endpoints.account("/account/api/oauth/exchange"))
         .bearer_auth(details.get_access_token().token)
-        .send()
-        .await?
     ).await?;
 
-    Ok(response.json::<ExchangeCode>().await?)
+    secret_json::<ExchangeCode>(response).await
 }
 
-#[allow(dead_code)]
-pub async fn exchange_to<'a, T:HasToken + HasIdentity+ for<'de> serde::Deserialize<'de>>(details:&T, exchange_to:&Client<'a>) -> Result<T, Box<dyn std::error::Error>>
+pub async fn exchange_to<T:HasToken + HasIdentity + HasSecrets + for<'de> serde::Deserialize<'de>>(context:&EpicContext, details:&T, exchange_to:&Client) -> Result<T, LauncherError>
 {
-    let code = exchange_code::<T>(details).await?;
+    let code = exchange_code::<T>(context, details).await?;
 
     let mut body = HashMap::new();
     body.insert("grant_type", "exchange_code");
     body.insert("exchange_code", code.code.as_str());
 
-    let response = handle_epic_response(
-        rest::CLIENT
-        .post(TOKEN)
-        .basic_auth(exchange_to.id, Some(exchange_to.secret))
+    let response = context.send_once(
+        context.http
+        .post(context.endpoints.account(TOKEN))
+        .basic_auth(&exchange_to.id, Some(&exchange_to.secret))
         .form(&body)
-        .send()
-        .await?
     ).await?;
 
-    Ok(response.json::<T>().await?)
+    secret_json::<T>(response).await
 }
 
-pub async fn login_with_authorization_code<'a>(code:&str, client:&Client<'a>) -> Result<AccountDetails, Box<dyn std::error::Error>>
+pub async fn login_with_authorization_code(context:&EpicContext, code:&str, client:&Client) -> Result<AccountDetails, LauncherError>
 {
+    redact::register(code);
+
     let mut body = HashMap::new();
     body.insert("grant_type", "authorization_code");
     body.insert("code", code);
 
-    let response = handle_epic_response(
-        rest::CLIENT
-        .post(TOKEN)
-        .basic_auth(client.id, Some(client.secret))
+    let response = context.send_once(
+        context.http
+        .post(context.endpoints.account(TOKEN))
+        .basic_auth(&client.id, Some(&client.secret))
         .form(&body)
-        .send()
-        .await?
     ).await?;
 
 
-    Ok(response.json::<AccountDetails>().await?)
+    secret_json::<AccountDetails>(response).await
 //    Ok(response.json::<Details>().await?)
 }
 
-pub async fn create_device_auth<T>(details:&T) -> Result<DeviceAuth, Box<dyn std::error::Error>>
+pub async fn create_device_auth<T>(context:&EpicContext, details:&T) -> Result<DeviceAuth, LauncherError>
 where T: HasToken + HasIdentity 
 {
-    let response = handle_epic_response(
-        rest::CLIENT
-        .post(format!("https://account-public-service-prod.ol.epicgames.com/account/api/public/account/{}/deviceAuth", details.get_account_id()))
-        .bearer_auth(details.get_access_token().token).send().await?
+    let response = context.send_once(
+        context.http
+        .post(context.endpoints.account(&format!("/account/api/public/account/{}/deviceAuth", details.get_account_id())))
+        .bearer_auth(details.get_access_token().token)
+    ).await?;
+
+    secret_json::<DeviceAuth>(response).await
+}
+
+pub async fn list_device_auths<T>(context:&EpicContext, details:&T) -> Result<Vec<DeviceAuthInfo>, LauncherError>
+where T: HasToken + HasIdentity
+{
+    let response = context.send(
+        context.http
+        .get(context.endpoints.account(&format!("/account/api/public/account/{}/deviceAuth", details.get_account_id())))
+        .bearer_auth(details.get_access_token().token)
+    ).await?;
+
+    Ok(response.json::<Vec<DeviceAuthInfo>>().await?)
+}
+
+pub async fn delete_device_auth<T>(context:&EpicContext, details:&T, device_id:&str) -> Result<(), LauncherError>
+where T: HasToken + HasIdentity
+{
+    context.send(
+        context.http
+        .delete(context.endpoints.account(&format!("/account/api/public/account/{}/deviceAuth/{}", details.get_account_id(), device_id)))
+        .bearer_auth(details.get_access_token().token)
     ).await?;
 
-    Ok(response.json::<DeviceAuth>().await?)
+    Ok(())
 }
 
-pub async fn login_with_device_auth<'a>(device_auth:&DeviceAuth, client:&Client<'a>) -> Result<AccountDetails, Box<dyn std::error::Error>>
+pub async fn login_with_device_auth(context:&EpicContext, device_auth:&DeviceAuth, client:&Client) -> Result<AccountDetails, LauncherError>
 {
     let mut body = HashMap::new();
     body.insert("grant_type", "device_auth");
@@ -327,31 +677,139 @@ pub async fn login_with_device_auth<'a>(device_auth:&DeviceAuth, client:&Client<
     body.insert("device_id", &device_auth.device_id);
     body.insert("secret", &device_auth.secret);
 
-    let response = handle_epic_response(
-        rest::CLIENT
-        .post(TOKEN)
-        .basic_auth(client.id, Some(client.secret))
+    let response = context.send_once(
+        context.http
+        .post(context.endpoints.account(TOKEN))
+        .basic_auth(&client.id, Some(&client.secret))
+        .form(&body)
+    ).await?;
+
+    secret_json::<AccountDetails>(response).await
+}
+
+pub async fn login_with_refresh_token(context:&EpicContext, refresh_token:&str, client:&Client) -> Result<AccountDetails, LauncherError>
+{
+    let mut body = HashMap::new();
+    body.insert("grant_type", "refresh_token");
+    body.insert("refresh_token", refresh_token);
+
+    //epic rotates the refresh token, a retried request would be refused once the first one went through
+    let response = context.send_once(
+        context.http
+        .post(context.endpoints.account(TOKEN))
+        .basic_auth(&client.id, Some(&client.secret))
         .form(&body)
-        .send().await?
     ).await?;
 
-    Ok(response.json::<AccountDetails>().await?)
+    secret_json::<AccountDetails>(response).await
+}
+
+pub async fn verify_token<T:HasToken>(context:&EpicContext, details:&T, include_perms:bool) -> Result<TokenVerification, LauncherError>
+{
+    let response = context.send(
+        context.http
+        .get(context.endpoints.account("/account/api/oauth/verify"))
+        .query(&[("includePerms", include_perms)])
+        .bearer_auth(details.get_access_token().token)
+    ).await?;
+
+    Ok(response.json::<TokenVerification>().await?)
+}
+
+pub async fn kill_sessions<T:HasToken>(context:&EpicContext, details:&T, kill_type:KillType) -> Result<(), LauncherError>
+{
+    context.send(
+        context.http
+        .delete(context.endpoints.account("/account/api/oauth/sessions/kill"))
+        .query(&[("killType", kill_type.as_str())])
+        .bearer_auth(details.get_access_token().token)
+    ).await?;
+
+    Ok(())
+}
+
+/// Kills the session of the given access token.
+pub async fn kill_session<T:HasToken>(context:&EpicContext, details:&T) -> Result<(), LauncherError>
+{
+    let token = details.get_access_token().token;
+
+    context.send(
+        context.http
+        .delete(context.endpoints.account(&format!("/account/api/oauth/sessions/kill/{}", token)))
+        .bearer_auth(&token)
+    ).await?;
+
+    Ok(())
 }
 
-pub async fn request_anti_cheat_provider<T>(details:&T) -> Result<AntiCheatProvider, Box<dyn std::error::Error>>
+pub async fn request_anti_cheat_provider<T>(context:&EpicContext, details:&T, options:&CalderaOptions) -> Result<AntiCheatProvider, LauncherError>
 where T:HasIdentity + HasToken
 {
-    let code = exchange_code(details).await?;
+    let code = exchange_code(context, details).await?;
     let mut body = AntiCheatProviderIn::default();
     body.account_id = details.get_account_id().to_string();
     body.exchange_code = code.code.to_string();
+    options.apply(&mut body);
 
-    let response = handle_epic_response(
-        rest::CLIENT
-        .post("https://caldera-service-prod.ecosec.on.epicgames.com/caldera/api/v1/launcher/racp")
-        .body(serde_json::to_string(&body)?)
-        .send().await?
+    tracing::info!(request = %serde_json::to_string(&body).unwrap_or_default(), "Requesting the anti cheat provider");
+
+    let response = context.send_once(
+        context.http
+        .post(context.endpoints.caldera("/caldera/api/v1/launcher/racp"))
+        .json(&body)
     ).await?;
 
-    Ok(response.json::<AntiCheatProvider>().await?)
-}
\ No newline at end of file
+    let provider = secret_json::<AntiCheatProvider>(response).await?;
+
+    tracing::info!(response = %serde_json::to_string(&provider).unwrap_or_default(), "Caldera picked {}", provider.provider);
+
+    Ok(provider)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: &str = "0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn parses_bare_code() {
+        assert_eq!(parse_authorization_code(CODE).unwrap(), CODE);
+        assert_eq!(parse_authorization_code(&format!("  \"{}\"\n", CODE)).unwrap(), CODE);
+    }
+
+    #[test]
+    fn parses_redirect_json() {
+        let json = format!(r#"{{"redirectUrl":"https://localhost/launcher/authorized?code={}","authorizationCode":null}}"#, CODE);
+        assert_eq!(parse_authorization_code(&json).unwrap(), CODE);
+
+        let json = format!(r#"{{"authorizationCode":"{}"}}"#, CODE);
+        assert_eq!(parse_authorization_code(&json).unwrap(), CODE);
+    }
+
+    #[test]
+    fn parses_redirect_url() {
+        let url = format!("https://localhost/launcher/authorized?foo=bar&code={}", CODE);
+        assert_eq!(parse_authorization_code(&url).unwrap(), CODE);
+    }
+
+    #[test]
+    fn parses_url_without_scheme() {
+        let url = format!("localhost/launcher/authorized?code={}", CODE);
+        assert_eq!(parse_authorization_code(&url).unwrap(), CODE);
+    }
+
+    #[test]
+    fn parses_query_string() {
+        assert_eq!(parse_authorization_code(&format!("?code={}", CODE)).unwrap(), CODE);
+        assert_eq!(parse_authorization_code(&format!("state=1&code={}", CODE)).unwrap(), CODE);
+    }
+
+    #[test]
+    fn rejects_invalid_codes() {
+        assert!(parse_authorization_code("not a code").is_err());
+        assert!(parse_authorization_code(&CODE[1..]).is_err());
+        assert!(parse_authorization_code(r#"{"authorizationCode":null}"#).is_err());
+        assert!(parse_authorization_code("https://localhost/launcher/authorized?state=1").is_err());
+    }
+}
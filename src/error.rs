@@ -0,0 +1,132 @@
+use std::path::PathBuf;
+
+use epic_manifest_parser_rs::error::ParseError;
+
+use crate::rest::EpicError;
+
+type Source = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug)]
+pub enum LauncherError {
+    /// The request could not be sent, or its response could not be read.
+    Http(reqwest::Error),
+    /// Epic answered with an error.
+    Epic(EpicError),
+    /// The configuration could not be read or written, or does not contain what was asked for.
+    Config { message: String, source: Option<Source> },
+    /// The Fortnite installation could not be found.
+    Install { message: String, source: Option<Source> },
+    Manifest { path: PathBuf, source: ParseError },
+    Process { path: String, source: Option<Source> },
+    /// A token could not be decoded, or can't be used anymore.
+    Token { message: String, source: Option<Source> },
+    /// What the user typed can't be used.
+    Input { message: String, source: Option<Source> },
+    /// A secret could not be read from or written to the secret store.
+    Secrets { message: String, source: Option<Source> },
+    Cancelled,
+}
+
+impl LauncherError {
+    pub fn config(message: impl Into<String>, source: impl Into<Source>) -> Self {
+        LauncherError::Config { message: message.into(), source: Some(source.into()) }
+    }
+
+    pub fn install(message: impl Into<String>, source: impl Into<Source>) -> Self {
+        LauncherError::Install { message: message.into(), source: Some(source.into()) }
+    }
+
+    pub fn process(path: impl Into<String>, source: impl Into<Source>) -> Self {
+        LauncherError::Process { path: path.into(), source: Some(source.into()) }
+    }
+
+    pub fn token(message: impl Into<String>, source: impl Into<Source>) -> Self {
+        LauncherError::Token { message: message.into(), source: Some(source.into()) }
+    }
+
+    pub fn input(message: impl Into<String>, source: impl Into<Source>) -> Self {
+        LauncherError::Input { message: message.into(), source: Some(source.into()) }
+    }
+
+    pub fn secrets(message: impl Into<String>, source: impl Into<Source>) -> Self {
+        LauncherError::Secrets { message: message.into(), source: Some(source.into()) }
+    }
+
+    /// No account of the configuration matches `name`.
+    pub fn account_not_found(name: &str) -> Self {
+        LauncherError::Config {
+            message: format!("No account named {} has been found", name),
+            source: None,
+        }
+    }
+
+    /// Returns true if epic refused the credentials because they have been revoked or have expired.
+    pub fn is_invalid_credentials(&self) -> bool {
+        matches!(
+            self,
+            LauncherError::Epic(EpicError::Unauthorized(_) | EpicError::InvalidGrant(_) | EpicError::Expired)
+        )
+    }
+
+    /// Exit code of the launcher when it stops because of this error.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            err if err.is_invalid_credentials() => 4,
+            LauncherError::Config { .. } => 2,
+            LauncherError::Http(_) => 3,
+            LauncherError::Epic(_) => 5,
+            LauncherError::Install { .. } => 6,
+            LauncherError::Manifest { .. } => 7,
+            LauncherError::Process { .. } => 8,
+            LauncherError::Input { .. } => 9,
+            LauncherError::Token { .. } => 10,
+            LauncherError::Secrets { .. } => 11,
+            LauncherError::Cancelled => 130,
+        }
+    }
+}
+
+impl std::fmt::Display for LauncherError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LauncherError::Http(_) => write!(f, "Failed to communicate with epic"),
+            LauncherError::Epic(err) => write!(f, "{}", err),
+            LauncherError::Config { message, .. } => write!(f, "Configuration error : {}", message),
+            LauncherError::Install { message, .. } => write!(f, "Installation error : {}", message),
+            LauncherError::Manifest { path, .. } => write!(f, "Failed to parse manifest {}", path.display()),
+            LauncherError::Process { path, .. } => write!(f, "Failed to start {}", path),
+            LauncherError::Input { message, .. } => write!(f, "{}", message),
+            LauncherError::Token { message, .. } => write!(f, "Token error : {}", message),
+            LauncherError::Secrets { message, .. } => write!(f, "Secret store error : {}", message),
+            LauncherError::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for LauncherError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LauncherError::Http(err) => Some(err),
+            LauncherError::Epic(_) | LauncherError::Cancelled => None,
+            LauncherError::Manifest { source, .. } => Some(source),
+            LauncherError::Config { source, .. }
+            | LauncherError::Install { source, .. }
+            | LauncherError::Process { source, .. }
+            | LauncherError::Token { source, .. }
+            | LauncherError::Input { source, .. }
+            | LauncherError::Secrets { source, .. } => source.as_deref().map(|err| err as &(dyn std::error::Error + 'static)),
+        }
+    }
+}
+
+impl From<reqwest::Error> for LauncherError {
+    fn from(err: reqwest::Error) -> Self {
+        LauncherError::Http(err)
+    }
+}
+
+impl From<EpicError> for LauncherError {
+    fn from(err: EpicError) -> Self {
+        LauncherError::Epic(err)
+    }
+}
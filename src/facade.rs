@@ -0,0 +1,625 @@
+use std::{future::Future, path::{Path, PathBuf}, process::Child};
+
+use crate::{
+    arguments::{LaunchArguments, LaunchOptions},
+    config::{AccountProfile, Configuration, DeviceAuth, SessionCache},
+    device_code,
+    epic::{
+        self, AccountDetails, AntiCheatKind, AntiCheatProvider, CalderaOptions, DeviceCode, HasClient, HasIdentity, HasRefreshToken,
+        HasToken, KillType, TokenVerification,
+    },
+    error::LauncherError,
+    import::{ImportSource, ImportedCredential},
+    launcher::{self, AntiCheatBinary},
+    rest::{EpicContext, EpicError},
+    session::Session,
+    windows,
+};
+
+/// A Fortnite installation, as found by `Launcher::resolve_install`.
+pub struct Install {
+    pub path: PathBuf,
+    pub start_command: Option<String>,
+}
+
+impl Install {
+    pub fn binaries(&self) -> PathBuf {
+        self.path.join("FortniteGame/Binaries/Win64")
+    }
+}
+
+/// Process started by `Launcher::spawn`.
+#[derive(Clone, Debug)]
+pub struct PlannedProcess {
+    pub path: PathBuf,
+    pub arguments: Option<LaunchArguments>,
+    pub suspended: bool,
+    /// The process is already running, it won't be started again.
+    pub running: bool,
+}
+
+/// Everything `Launcher::spawn` would do, in order : the helper processes, then the game.
+#[derive(Clone, Debug)]
+pub struct LaunchPlan {
+    pub install: PathBuf,
+    pub start_command: Option<String>,
+    pub provider: AntiCheatKind,
+    pub anti_cheat: AntiCheatBinary,
+    pub helpers: Vec<PlannedProcess>,
+    pub game: PlannedProcess,
+}
+
+/// Owns the configuration and the session cache, and exposes each step needed to start Fortnite.
+/// Nothing is saved when it is dropped, call `save` once done.
+pub struct Launcher {
+    configuration: Configuration,
+    cache: SessionCache,
+}
+
+/// Makes sure an imported credential logs into the account it has been read for.
+fn check_imported_account(account_id: &str, details: &AccountDetails) -> Result<(), LauncherError> {
+    if details.get_account_id() == account_id {
+        return Ok(());
+    }
+
+    Err(LauncherError::Input {
+        message: format!("The credential of {} belongs to the account {}", account_id, details.get_account_id()),
+        source: None,
+    })
+}
+
+/// Refuses caldera tokens which have already expired, the anti cheat would not let the game start with them.
+/// Tokens which can't be decoded are let through, caldera may change their format.
+fn check_caldera(anti_cheat: &AntiCheatProvider) -> Result<(), LauncherError> {
+    let jwt = match anti_cheat.decode_jwt() {
+        Ok(jwt) => jwt,
+        Err(err) => {
+            tracing::warn!(error = %err, "Failed to decode the caldera token");
+            return Ok(());
+        }
+    };
+
+    if let Some(expiration) = jwt.expires_at().filter(|_| jwt.is_expired()) {
+        return Err(LauncherError::Token {
+            message: format!("The caldera token expired at {}, request a new one", expiration),
+            source: None,
+        });
+    }
+
+    if let Some(provider) = jwt.provider().filter(|provider| *provider != anti_cheat.provider) {
+        tracing::warn!("The caldera token is for {} but caldera answered {}", provider, anti_cheat.provider);
+    }
+
+    Ok(())
+}
+
+fn path_str(path: &Path) -> Result<&str, LauncherError> {
+    path.to_str().ok_or_else(|| LauncherError::Process {
+        path: path.display().to_string(),
+        source: Some("The path is not valid unicode".into()),
+    })
+}
+
+impl Launcher {
+    pub fn new(configuration: Configuration, cache: SessionCache) -> Self {
+        Self { configuration, cache }
+    }
+
+    /// Reads the configuration, see `Configuration::locate`, and the session cache stored next to it.
+    pub async fn open(config: Option<&Path>) -> Result<Self, LauncherError> {
+        let config = config.map(PathBuf::from);
+
+        //waiting for the lock of the configuration or for the passphrase of its secrets blocks the thread
+        tokio::task::spawn_blocking(move || {
+            let configuration = Configuration::open(config.as_deref())?;
+            let cache = configuration.session_cache()?;
+
+            Ok(Self::new(configuration, cache))
+        })
+        .await
+        .map_err(|err| LauncherError::config("Failed to open the configuration", err))?
+    }
+
+    /// Writes the configuration and the session cache if they have changed.
+    /// Nothing is saved when the launcher is dropped, call it once done.
+    pub fn save(&mut self) -> Result<(), LauncherError> {
+        self.configuration.flush()?;
+        self.cache.flush()
+    }
+
+    pub fn configuration(&self) -> &Configuration {
+        &self.configuration
+    }
+
+    pub fn configuration_mut(&mut self) -> &mut Configuration {
+        &mut self.configuration
+    }
+
+    pub fn cache_mut(&mut self) -> &mut SessionCache {
+        &mut self.cache
+    }
+
+    pub fn context(&self) -> EpicContext {
+        self.configuration.context()
+    }
+
+    /// Url where the user gets an authorization code for the onboarding client.
+    pub fn authorization_code_url(&self) -> Result<String, LauncherError> {
+        let clients = self.configuration.login_clients()?;
+
+        Ok(self
+            .configuration
+            .endpoints
+            .website(&format!("/id/api/redirect?clientId={}&responseType=code", clients.onboarding.id)))
+    }
+
+    /// Adds an account from an authorization code, `code` may be the whole redirect page or url.
+    /// Returns its details on the onboarding client.
+    pub async fn add_account_with_authorization_code(
+        &mut self,
+        name: Option<&str>,
+        code: &str,
+    ) -> Result<AccountDetails, LauncherError> {
+        let context = self.context();
+        let clients = self.configuration.login_clients()?;
+
+        let authorization_code = epic::parse_authorization_code(code)?;
+        let details = epic::login_with_authorization_code(&context, &authorization_code, &clients.onboarding).await?;
+
+        self.add_account(name, &details).await?;
+
+        Ok(details)
+    }
+
+    /// Creates a device code the user has to enter, to be passed to `add_account_with_device_code`.
+    pub async fn create_device_code(&self) -> Result<DeviceCode, LauncherError> {
+        let context = self.context();
+        let clients = self.configuration.login_clients()?;
+
+        let details = epic::client_credentials(&context, &clients.device_code).await?;
+
+        epic::create_device_code(&context, &details).await
+    }
+
+    /// Waits for the user to enter the device code, gives up when `cancel` completes.
+    /// Returns the details of the added account on the onboarding client.
+    pub async fn add_account_with_device_code<F>(
+        &mut self,
+        name: Option<&str>,
+        device_code: DeviceCode,
+        cancel: F,
+    ) -> Result<AccountDetails, LauncherError>
+    where
+        F: Future,
+    {
+        let context = self.context();
+        let clients = self.configuration.login_clients()?;
+
+        let details = device_code::wait_for_authorization(context.clone(), device_code, clients.device_code, cancel).await?;
+        let details = epic::exchange_to(&context, &details, &clients.onboarding).await?;
+
+        self.add_account(name, &details).await?;
+
+        Ok(details)
+    }
+
+    async fn add_account(&mut self, name: Option<&str>, details: &AccountDetails) -> Result<(), LauncherError> {
+        let device_auth = epic::create_device_auth(&self.context(), details).await?;
+
+        self.configuration
+            .add_account(name.unwrap_or(details.get_display_name()), details.get_display_name(), device_auth);
+
+        Ok(())
+    }
+
+    /// Logs into an account by profile name, display name or account id, reusing its cached session when possible.
+    pub async fn authenticate(&mut self, account: &str) -> Result<Session, LauncherError> {
+        let device_auth = self
+            .configuration
+            .find_account(account)
+            .ok_or_else(|| LauncherError::account_not_found(account))?
+            .device_auth
+            .clone();
+        let clients = self.configuration.login_clients()?;
+
+        let details = match self.cache.get(&device_auth.account_id, &clients.launch.id) {
+            Some(details) => {
+                tracing::debug!(account_id = %device_auth.account_id, "Reusing the cached session");
+                details
+            }
+            None => {
+                tracing::debug!(account_id = %device_auth.account_id, "Logging in with the device auth");
+                epic::login_with_device_auth(&self.context(), &device_auth, &clients.onboarding).await?
+            }
+        };
+
+        self.start_session(details).await
+    }
+
+    /// Turns the details of a stored account, on any client, into a session on the launch client and caches it.
+    pub async fn start_session(&mut self, details: AccountDetails) -> Result<Session, LauncherError> {
+        let context = self.context();
+        let clients = self.configuration.login_clients()?;
+
+        let details = if details.get_client_id() == clients.launch.id {
+            details
+        } else {
+            epic::exchange_to(&context, &details, &clients.launch).await?
+        };
+
+        self.configuration.touch_account(details.get_account_id());
+        let device_auth = self
+            .configuration
+            .find_account_by_id(details.get_account_id())
+            .map(|account| account.device_auth.clone());
+
+        let mut session = Session::new(context, details, clients, device_auth);
+        if let Err(err) = session.renew_if_needed().await {
+            //the cached session can't be renewed, don't try it again on the next launch
+            self.cache.invalidate(session.get_account_id(), session.get_client_id());
+            return Err(err);
+        }
+        self.cache.insert(session.details());
+
+        Ok(session)
+    }
+
+    /// Logs into a new session from scratch, for when the cached one has been revoked server-side.
+    async fn restart_session(&mut self, session: &mut Session) -> Result<(), LauncherError> {
+        self.cache.invalidate(session.get_account_id(), session.get_client_id());
+
+        let account_id = session.get_account_id().to_string();
+        *session = self.authenticate(&account_id).await?;
+
+        Ok(())
+    }
+
+    /// Finds an account by name, or the default one, and logs into it with its device auth on the onboarding client.
+    pub async fn device_auth_login(
+        &self,
+        account: Option<&str>,
+    ) -> Result<(AccountProfile, AccountDetails), LauncherError> {
+        let profile = match account {
+            Some(name) => self.configuration.find_account(name),
+            None => self.configuration.get_default_account(),
+        }
+        .ok_or_else(|| LauncherError::account_not_found(account.unwrap_or("default")))?
+        .clone();
+
+        let details = epic::login_with_device_auth(
+            &self.context(),
+            &profile.device_auth,
+            &self.configuration.login_clients()?.onboarding,
+        )
+        .await?;
+
+        Ok((profile, details))
+    }
+
+    /// Imports the credentials of another launcher read from `path`, or from its default location.
+    /// Each account is only stored once epic accepted its credential, the outcome is given per account id
+    /// so one failure doesn't stop the others.
+    pub async fn import_accounts(
+        &mut self,
+        source: ImportSource,
+        path: Option<&Path>,
+    ) -> Result<Vec<(String, Result<AccountProfile, LauncherError>)>, LauncherError> {
+        let mut outcomes = Vec::new();
+
+        for credential in source.read(path)? {
+            let account_id = credential.account_id().to_string();
+            let outcome = self.import_credential(credential).await;
+
+            if let Err(err) = &outcome {
+                tracing::warn!(%source, account_id, error = %err, "Failed to import an account");
+            }
+            outcomes.push((account_id, outcome));
+        }
+
+        Ok(outcomes)
+    }
+
+    async fn import_credential(&mut self, credential: ImportedCredential) -> Result<AccountProfile, LauncherError> {
+        let context = self.context();
+        let clients = self.configuration.login_clients()?;
+        let account_id = credential.account_id().to_string();
+
+        let device_auth = match credential {
+            ImportedCredential::RefreshToken { refresh_token, client_id, .. } => {
+                //a refresh token can only be used by the client it has been issued to
+                let registry = self.configuration.client_registry();
+                let client = match client_id {
+                    Some(id) => registry.find_by_id(&id).ok_or_else(|| LauncherError::Config {
+                        message: format!("The session has been issued to the unknown client {}, add it to the clients of config.json", id),
+                        source: None,
+                    })?,
+                    None => registry.get(epic::LAUNCHER_APP_CLIENT_2)?,
+                };
+
+                //checked before creating the device auth, it would be left behind on the wrong account
+                let details = epic::login_with_refresh_token(&context, &refresh_token, client).await?;
+                check_imported_account(&account_id, &details)?;
+
+                let details = epic::exchange_to(&context, &details, &clients.onboarding).await?;
+                epic::create_device_auth(&context, &details).await?
+            }
+            ImportedCredential::DeviceAuth { device_auth, .. } => device_auth,
+        };
+
+        let details = epic::login_with_device_auth(&context, &device_auth, &clients.onboarding).await?;
+        check_imported_account(&account_id, &details)?;
+
+        //an account which is already stored keeps its profile name
+        let name = self
+            .configuration
+            .find_account_by_id(&account_id)
+            .map(|profile| profile.name.clone())
+            .unwrap_or_else(|| details.get_display_name().to_string());
+        self.configuration.add_account(&name, details.get_display_name(), device_auth);
+
+        self.configuration
+            .find_account_by_id(&account_id)
+            .cloned()
+            .ok_or_else(|| LauncherError::account_not_found(&account_id))
+    }
+
+    /// Creates a new device auth for the account, stores it and then revokes the previous one.
+    pub async fn rotate_device_auth(&mut self, account: Option<&str>) -> Result<DeviceAuth, LauncherError> {
+        let (profile, details) = self.device_auth_login(account).await?;
+        let context = self.context();
+
+        let device_auth = epic::create_device_auth(&context, &details).await?;
+        self.configuration.set_device_auth(device_auth.clone());
+        self.configuration.flush()?;
+
+        epic::delete_device_auth(&context, &details, &profile.device_auth.device_id).await?;
+
+        Ok(device_auth)
+    }
+
+    /// Revokes the device auth of an account and forgets it, then kills its cached session on this device,
+    /// or every session of the account if `everywhere` is set.
+    /// When the device auth has already been revoked, the account is still forgotten.
+    pub async fn logout(&mut self, account: Option<&str>, everywhere: bool) -> Result<AccountProfile, LauncherError> {
+        let profile = match account {
+            Some(name) => self.configuration.find_account(name),
+            None => self.configuration.get_default_account(),
+        }
+        .ok_or_else(|| LauncherError::account_not_found(account.unwrap_or("default")))?
+        .clone();
+        let account_id = profile.device_auth.account_id.clone();
+        let context = self.context();
+        let clients = self.configuration.login_clients()?;
+
+        //the session used to launch the game, the one created by the device auth login below is not the one to kill
+        let cached = self.cache.get(&account_id, &clients.launch.id);
+
+        let details = match epic::login_with_device_auth(&context, &profile.device_auth, &clients.onboarding).await {
+            Ok(details) => {
+                epic::delete_device_auth(&context, &details, &profile.device_auth.device_id).await?;
+                Some(details)
+            }
+            Err(err) if err.is_invalid_credentials() => {
+                tracing::warn!(%account_id, error = %err, "The device auth has already been revoked, forgetting the account");
+                None
+            }
+            Err(err) => return Err(err),
+        };
+
+        self.configuration.remove_account(&account_id);
+        self.cache.invalidate_account(&account_id);
+
+        let killed = Self::end_sessions(&context, &clients.launch, details, cached, everywhere).await;
+
+        match killed {
+            Err(err) if err.is_invalid_credentials() => {
+                tracing::warn!(%account_id, error = %err, "The session has already ended");
+                Ok(profile)
+            }
+            Err(err) => Err(err),
+            Ok(()) => Ok(profile),
+        }
+    }
+
+    /// Kills the cached session, or every session of the account with `everywhere`
+    /// using whichever token is available.
+    async fn end_sessions(
+        context: &EpicContext,
+        launch: &epic::Client,
+        details: Option<AccountDetails>,
+        cached: Option<AccountDetails>,
+        everywhere: bool,
+    ) -> Result<(), LauncherError> {
+        if everywhere {
+            let details = match (details, cached) {
+                (Some(details), _) => details,
+                (None, Some(cached)) => Self::renewed(context, cached, launch).await?,
+                (None, None) => return Ok(()),
+            };
+
+            return epic::kill_sessions(context, &details, KillType::All).await;
+        }
+
+        match cached {
+            Some(cached) => epic::kill_session(context, &Self::renewed(context, cached, launch).await?).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Cached details with a usable access token, renewed with the refresh token if it has expired.
+    async fn renewed(context: &EpicContext, details: AccountDetails, client: &epic::Client) -> Result<AccountDetails, LauncherError> {
+        if !details.get_access_token().is_expired() {
+            return Ok(details);
+        }
+
+        epic::login_with_refresh_token(context, &details.get_refresh_token().token, client).await
+    }
+
+    /// Asks epic who the session belongs to, logging in again if the session has been revoked.
+    pub async fn verify(&mut self, session: &mut Session) -> Result<TokenVerification, LauncherError> {
+        let context = self.context();
+
+        match epic::verify_token(&context, session, false).await {
+            Err(LauncherError::Epic(EpicError::Unauthorized(_))) => {
+                self.restart_session(session).await?;
+
+                epic::verify_token(&context, session, false).await
+            }
+            result => result,
+        }
+    }
+
+    /// Caldera options of an account, overridden by the ones set in `overrides`.
+    pub fn caldera_options(&self, account_id: &str, overrides: &CalderaOptions) -> CalderaOptions {
+        self.configuration
+            .find_account_by_id(account_id)
+            .map(|account| account.caldera.merge(overrides))
+            .unwrap_or_else(|| overrides.clone())
+    }
+
+    /// Asks caldera which anti cheat to start, logging in again if the session has been revoked.
+    /// The caldera options of the account are sent, overridden by the ones set in `overrides`.
+    pub async fn request_anti_cheat_provider(
+        &mut self,
+        session: &mut Session,
+        overrides: &CalderaOptions,
+    ) -> Result<AntiCheatProvider, LauncherError> {
+        let context = self.context();
+        let options = self.caldera_options(session.get_account_id(), overrides);
+
+        match epic::request_anti_cheat_provider(&context, session, &options).await {
+            Err(LauncherError::Epic(EpicError::Unauthorized(_))) => {
+                self.restart_session(session).await?;
+
+                epic::request_anti_cheat_provider(&context, session, &options).await
+            }
+            result => result,
+        }
+    }
+
+    /// Finds the Fortnite installation, from the configuration or from the Epic Games Launcher,
+    /// and remembers it in the configuration.
+    pub fn resolve_install(&mut self) -> Result<Install, LauncherError> {
+        let configured = self
+            .configuration
+            .fortnite_path
+            .as_ref()
+            .map(PathBuf::from)
+            .filter(|path| path.exists());
+
+        let path = match configured {
+            Some(path) => path,
+            None => match launcher::get_launcher_installed()?.find("Fortnite") {
+                Some(entry) => {
+                    self.configuration.fortnite_path = Some(entry.install_location.clone());
+                    PathBuf::from(&entry.install_location)
+                }
+                None => {
+                    return Err(LauncherError::Install {
+                        message: String::from("No Fortnite Path has been found, please fill it in config.json."),
+                        source: None,
+                    })
+                }
+            },
+        };
+
+        let start_command = launcher::find_start_command(&path)?;
+        tracing::info!(path = %path.display(), start_command = start_command.as_deref().unwrap_or_default(), "Resolved the Fortnite installation");
+
+        Ok(Install { path, start_command })
+    }
+
+    /// Creates the exchange code Fortnite logs in with and returns its command line : the built-in arguments,
+    /// changed by the launch options of the account, then by `overrides`.
+    /// Fails if the caldera token has already expired.
+    pub async fn build_arguments(
+        &mut self,
+        session: &mut Session,
+        install: &Install,
+        anti_cheat: &AntiCheatProvider,
+        overrides: &LaunchOptions,
+    ) -> Result<LaunchArguments, LauncherError> {
+        if session.renew_if_needed().await? {
+            self.cache.insert(session.details());
+        }
+
+        check_caldera(anti_cheat)?;
+
+        let binary = self.configuration.anti_cheat_registry().get(&anti_cheat.provider)?.clone();
+        let exchange_code = epic::exchange_code(&self.context(), session).await?;
+        tracing::debug!("Created exchange code successfully");
+
+        let mut arguments =
+            launcher::generate_arguments(session, &exchange_code, anti_cheat, &binary, install.start_command.as_ref());
+
+        if let Some(account) = self.configuration.find_account_by_id(session.get_account_id()) {
+            account.launch.apply(&mut arguments);
+        }
+        overrides.apply(&mut arguments);
+
+        Ok(arguments)
+    }
+
+    /// Resolves the processes `spawn` would start, without starting them.
+    pub fn plan(&self, install: &Install, anti_cheat: &AntiCheatProvider, arguments: &LaunchArguments) -> Result<LaunchPlan, LauncherError> {
+        let binary = self.configuration.anti_cheat_registry().get(&anti_cheat.provider)?.clone();
+        let fortnite_binary_folder = install.binaries();
+        if !fortnite_binary_folder.exists()
+        {
+            return Err(LauncherError::Install { message: String::from("Fortnite Binary does not exists !"), source: None });
+        }
+
+        //the launcher and the anti cheat only have to exist, they are started suspended
+        let helpers = ["FortniteLauncher.exe", binary.executable.as_str()]
+            .iter()
+            .map(|name| PlannedProcess {
+                path: fortnite_binary_folder.join(name),
+                arguments: None,
+                suspended: true,
+                running: unsafe { windows::find_process(name) }.is_some(),
+            })
+            .collect();
+
+        let game = PlannedProcess {
+            path: fortnite_binary_folder.join("FortniteClient-Win64-Shipping.exe"),
+            arguments: Some(arguments.clone()),
+            suspended: false,
+            running: false,
+        };
+
+        Ok(LaunchPlan {
+            install: install.path.clone(),
+            start_command: install.start_command.clone(),
+            provider: anti_cheat.provider.clone(),
+            anti_cheat: binary,
+            helpers,
+            game,
+        })
+    }
+
+    /// Starts FortniteLauncher.exe and the anti cheat if they are not running yet, then the game itself.
+    pub fn spawn(&self, install: &Install, anti_cheat: &AntiCheatProvider, arguments: &LaunchArguments) -> Result<Child, LauncherError> {
+        let plan = self.plan(install, anti_cheat, arguments)?;
+
+        for helper in plan.helpers.iter().filter(|helper| !helper.running) {
+            tracing::info!(path = %helper.path.display(), suspended = helper.suspended, "Starting helper process");
+            launcher::create_process(path_str(&helper.path)?, None, helper.suspended)?;
+        }
+
+        tracing::info!(path = %plan.game.path.display(), "Starting Fortnite");
+        let game = launcher::spawn_child(path_str(&plan.game.path)?, plan.game.arguments.as_ref())?;
+        tracing::info!(pid = game.id(), "Created Fortnite process");
+
+        Ok(game)
+    }
+
+    /// Runs the whole flow for a stored account and returns the Fortnite process.
+    pub async fn launch(&mut self, account: &str) -> Result<Child, LauncherError> {
+        let mut session = self.authenticate(account).await?;
+        let anti_cheat = self.request_anti_cheat_provider(&mut session, &CalderaOptions::default()).await?;
+        let install = self.resolve_install()?;
+        let arguments = self.build_arguments(&mut session, &install, &anti_cheat, &LaunchOptions::default()).await?;
+
+        self.spawn(&install, &anti_cheat, &arguments)
+    }
+}
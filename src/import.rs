@@ -0,0 +1,252 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+use crate::{config::DeviceAuth, error::LauncherError, redact};
+
+/// Launcher whose credentials can be imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportSource {
+    Legendary,
+    Heroic,
+    LlamaLauncher,
+}
+
+impl std::str::FromStr for ImportSource {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "legendary" => Ok(ImportSource::Legendary),
+            "heroic" => Ok(ImportSource::Heroic),
+            "llama" | "llamalauncher" | "llama-launcher" => Ok(ImportSource::LlamaLauncher),
+            _ => Err("Invalid import source, expected legendary, heroic or llama-launcher"),
+        }
+    }
+}
+
+impl std::fmt::Display for ImportSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportSource::Legendary => write!(f, "Legendary"),
+            ImportSource::Heroic => write!(f, "Heroic"),
+            ImportSource::LlamaLauncher => write!(f, "LlamaLauncher"),
+        }
+    }
+}
+
+impl ImportSource {
+    /// File where the launcher stores its credentials, LlamaLauncher has no fixed location.
+    pub fn default_path(&self) -> Option<PathBuf> {
+        match self {
+            //legendary uses ~/.config on every platform, unless XDG_CONFIG_HOME is set
+            ImportSource::Legendary => std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| dirs::home_dir().map(|home| home.join(".config")))
+                .map(|folder| folder.join("legendary").join("user.json")),
+            ImportSource::Heroic => dirs::config_dir()
+                .map(|folder| folder.join("heroic").join("legendaryConfig").join("legendary").join("user.json")),
+            ImportSource::LlamaLauncher => None,
+        }
+    }
+
+    /// Reads the credentials stored at `path`, or at the default path of the launcher.
+    pub fn read(&self, path: Option<&Path>) -> Result<Vec<ImportedCredential>, LauncherError> {
+        let path = path.map(PathBuf::from).or_else(|| self.default_path()).ok_or_else(|| LauncherError::Input {
+            message: format!("{} has no default location, give the path of its device auths", self),
+            source: None,
+        })?;
+
+        let data_str = std::fs::read_to_string(&path)
+            .map_err(|err| LauncherError::input(format!("Failed to read {}", path.display()), err))?;
+        let value: Value = serde_json::from_str(&data_str)
+            .map_err(|err| LauncherError::input(format!("Failed to parse {}", path.display()), err))?;
+
+        let credentials = match self {
+            ImportSource::Legendary | ImportSource::Heroic => vec![refresh_token_session(&value)?],
+            ImportSource::LlamaLauncher => device_auths(&value),
+        };
+
+        if credentials.is_empty() {
+            return Err(LauncherError::Input {
+                message: format!("No credential has been found in {}", path.display()),
+                source: None,
+            });
+        }
+
+        tracing::info!(source = %self, path = %path.display(), count = credentials.len(), "Read credentials to import");
+
+        Ok(credentials)
+    }
+}
+
+/// Credential read from another launcher, not verified yet.
+#[derive(Clone)]
+pub enum ImportedCredential {
+    /// Session of the Epic Games Launcher client, as stored by Legendary and Heroic.
+    RefreshToken {
+        account_id: String,
+        display_name: Option<String>,
+        client_id: Option<String>,
+        refresh_token: String,
+    },
+    DeviceAuth {
+        display_name: Option<String>,
+        device_auth: DeviceAuth,
+    },
+}
+
+impl ImportedCredential {
+    pub fn account_id(&self) -> &str {
+        match self {
+            ImportedCredential::RefreshToken { account_id, .. } => account_id,
+            ImportedCredential::DeviceAuth { device_auth, .. } => &device_auth.account_id,
+        }
+    }
+
+    pub fn display_name(&self) -> Option<&str> {
+        match self {
+            ImportedCredential::RefreshToken { display_name, .. } | ImportedCredential::DeviceAuth { display_name, .. } => {
+                display_name.as_deref()
+            }
+        }
+    }
+}
+
+fn string(value: &Value, names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .find_map(|name| value.get(name))
+        .and_then(Value::as_str)
+        .filter(|value| !value.is_empty())
+        .map(String::from)
+}
+
+/// user.json of Legendary, the token response of the last login.
+fn refresh_token_session(value: &Value) -> Result<ImportedCredential, LauncherError> {
+    let invalid = |message: &str| LauncherError::Input { message: message.to_string(), source: None };
+
+    let refresh_token = string(value, &["refresh_token"]).ok_or_else(|| invalid("The session has no refresh token, login again in the other launcher"))?;
+    let account_id = string(value, &["account_id"]).ok_or_else(|| invalid("The session has no account id"))?;
+    redact::register(&refresh_token);
+
+    let expired = string(value, &["refresh_expires_at"])
+        .and_then(|date| DateTime::parse_from_rfc3339(&date).ok())
+        .is_some_and(|date| date <= Utc::now());
+    if expired {
+        return Err(invalid("The session has expired, login again in the other launcher"));
+    }
+
+    Ok(ImportedCredential::RefreshToken {
+        account_id,
+        display_name: string(value, &["displayName", "display_name"]),
+        client_id: string(value, &["client_id"]),
+        refresh_token,
+    })
+}
+
+fn device_auth(value: &Value) -> Option<ImportedCredential> {
+    let device_auth = DeviceAuth {
+        account_id: string(value, &["accountId", "account_id", "AccountId"])?,
+        device_id: string(value, &["deviceId", "device_id", "DeviceId"])?,
+        secret: string(value, &["secret", "Secret"])?,
+        secret_ref: None,
+    };
+    redact::register_all(&device_auth);
+
+    Some(ImportedCredential::DeviceAuth {
+        display_name: string(value, &["displayName", "display_name", "DisplayName"]),
+        device_auth,
+    })
+}
+
+/// Device auths of LlamaLauncher : a single device auth, a list of them, or an object of them keyed by account.
+fn device_auths(value: &Value) -> Vec<ImportedCredential> {
+    if let Some(credential) = device_auth(value) {
+        return vec![credential];
+    }
+
+    match value {
+        Value::Array(values) => values.iter().filter_map(device_auth).collect(),
+        Value::Object(values) => values.values().filter_map(device_auth).collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn account_ids(credentials: &[ImportedCredential]) -> Vec<&str> {
+        credentials.iter().map(ImportedCredential::account_id).collect()
+    }
+
+    #[test]
+    fn reads_legendary_session() {
+        let session = json!({
+            "account_id": "abc",
+            "displayName": "Name",
+            "client_id": "client",
+            "refresh_token": "refresh",
+            "refresh_expires_at": "2999-01-01T00:00:00.000Z",
+        });
+
+        let ImportedCredential::RefreshToken { account_id, display_name, client_id, refresh_token } = refresh_token_session(&session).unwrap() else {
+            panic!("Expected a refresh token");
+        };
+        assert_eq!(account_id, "abc");
+        assert_eq!(display_name.as_deref(), Some("Name"));
+        assert_eq!(client_id.as_deref(), Some("client"));
+        assert_eq!(refresh_token, "refresh");
+    }
+
+    #[test]
+    fn rejects_expired_legendary_session() {
+        let session = json!({
+            "account_id": "abc",
+            "refresh_token": "refresh",
+            "refresh_expires_at": "2020-01-01T00:00:00.000Z",
+        });
+        assert!(refresh_token_session(&session).is_err());
+
+        assert!(refresh_token_session(&json!({"account_id": "abc", "refresh_token": ""})).is_err());
+        assert!(refresh_token_session(&json!({"refresh_token": "refresh"})).is_err());
+    }
+
+    #[test]
+    fn reads_single_device_auth() {
+        let credentials = device_auths(&json!({"accountId": "abc", "deviceId": "device", "secret": "secret", "displayName": "Name"}));
+
+        assert_eq!(account_ids(&credentials), ["abc"]);
+        assert_eq!(credentials[0].display_name(), Some("Name"));
+        let ImportedCredential::DeviceAuth { device_auth, .. } = &credentials[0] else {
+            panic!("Expected a device auth");
+        };
+        assert_eq!(device_auth.device_id, "device");
+        assert_eq!(device_auth.secret, "secret");
+    }
+
+    #[test]
+    fn reads_device_auth_list() {
+        let credentials = device_auths(&json!([
+            {"accountId": "abc", "deviceId": "device", "secret": "secret"},
+            {"AccountId": "def", "DeviceId": "device", "Secret": "secret"},
+            {"accountId": "incomplete"},
+        ]));
+
+        assert_eq!(account_ids(&credentials), ["abc", "def"]);
+    }
+
+    #[test]
+    fn reads_device_auths_by_account() {
+        let credentials = device_auths(&json!({
+            "abc": {"account_id": "abc", "device_id": "device", "secret": "secret"},
+        }));
+        assert_eq!(account_ids(&credentials), ["abc"]);
+
+        assert!(device_auths(&json!("not a device auth")).is_empty());
+    }
+}
@@ -0,0 +1,190 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+
+use crate::{epic::AntiCheatKind, error::LauncherError};
+
+/// Prefix of the access tokens issued by epic, the JWT follows it.
+const EPIC_TOKEN_PREFIX: &str = "eg1~";
+
+/// Header and claims of a JWT, decoded without verifying its signature.
+/// Only use it to inspect tokens, never to trust them.
+#[derive(Clone, Debug)]
+pub struct Jwt {
+    pub header: Map<String, Value>,
+    pub claims: Map<String, Value>,
+}
+
+fn decode_part(part: &str) -> Result<Map<String, Value>, LauncherError> {
+    //some issuers keep the padding, the url safe alphabet doesn't need it
+    let bytes = URL_SAFE_NO_PAD
+        .decode(part.trim_end_matches('='))
+        .map_err(|err| LauncherError::token("The token is not valid base64", err))?;
+
+    serde_json::from_slice(&bytes).map_err(|err| LauncherError::token("The token does not contain a JSON object", err))
+}
+
+fn timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(value.as_i64()?, 0)
+}
+
+impl Jwt {
+    /// Decodes a JWT, or an epic access token starting with `eg1~`.
+    pub fn decode(token: &str) -> Result<Self, LauncherError> {
+        let token = token.trim();
+        let token = token.strip_prefix(EPIC_TOKEN_PREFIX).unwrap_or(token);
+
+        let mut parts = token.split('.');
+        let (Some(header), Some(claims), Some(_signature), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(LauncherError::Token {
+                message: String::from("A JWT is made of three parts separated by dots"),
+                source: None,
+            });
+        };
+
+        Ok(Self {
+            header: decode_part(header)?,
+            claims: decode_part(claims)?,
+        })
+    }
+
+    pub fn claim(&self, name: &str) -> Option<&Value> {
+        self.claims.get(name)
+    }
+
+    fn claim_str(&self, name: &str) -> Option<&str> {
+        self.claim(name).and_then(Value::as_str)
+    }
+
+    pub fn algorithm(&self) -> Option<&str> {
+        self.header.get("alg").and_then(Value::as_str)
+    }
+
+    pub fn issued_at(&self) -> Option<DateTime<Utc>> {
+        self.claim("iat").and_then(timestamp)
+    }
+
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.claim("exp").and_then(timestamp)
+    }
+
+    /// Returns true if the token has an expiration date and it has passed.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at().is_some_and(|expiration| expiration <= Utc::now())
+    }
+
+    /// `sub` for access tokens, `account_id` for caldera.
+    pub fn account_id(&self) -> Option<&str> {
+        self.claim_str("sub").or_else(|| self.claim_str("account_id"))
+    }
+
+    pub fn client_id(&self) -> Option<&str> {
+        self.claim_str("clid")
+    }
+
+    pub fn display_name(&self) -> Option<&str> {
+        self.claim_str("dn")
+    }
+
+    /// Scopes of the token, given either as a space separated string or as an array.
+    pub fn scopes(&self) -> Vec<String> {
+        match self.claim("scope") {
+            Some(Value::String(scopes)) => scopes.split_whitespace().map(String::from).collect(),
+            Some(Value::Array(scopes)) => scopes.iter().filter_map(Value::as_str).map(String::from).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Anti cheat provider of a caldera token.
+    pub fn provider(&self) -> Option<AntiCheatKind> {
+        self.claim_str("acProvider").map(|provider| AntiCheatKind::from(provider.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::engine::general_purpose::URL_SAFE;
+    use serde_json::json;
+
+    use super::*;
+
+    fn token(claims: Value) -> String {
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256","typ":"JWT"}"#);
+        let claims = URL_SAFE_NO_PAD.encode(claims.to_string());
+
+        format!("{}.{}.signature", header, claims)
+    }
+
+    #[test]
+    fn decodes_token() {
+        let jwt = Jwt::decode(&token(json!({"sub": "abc", "clid": "client", "dn": "Name", "iat": 1700000000}))).unwrap();
+
+        assert_eq!(jwt.algorithm(), Some("RS256"));
+        assert_eq!(jwt.account_id(), Some("abc"));
+        assert_eq!(jwt.client_id(), Some("client"));
+        assert_eq!(jwt.display_name(), Some("Name"));
+        assert_eq!(jwt.issued_at(), DateTime::from_timestamp(1700000000, 0));
+    }
+
+    #[test]
+    fn decodes_epic_token() {
+        let jwt = Jwt::decode(&format!("eg1~{}", token(json!({"sub": "abc"})))).unwrap();
+
+        assert_eq!(jwt.account_id(), Some("abc"));
+    }
+
+    #[test]
+    fn decodes_padded_parts() {
+        //17 bytes of JSON, encoded with one padding character
+        let claims = r#"{"sub":"abcdefg"}"#;
+        let padded = URL_SAFE.encode(claims);
+        assert!(padded.ends_with('='));
+
+        let jwt = Jwt::decode(&format!("{}.{}.signature", URL_SAFE.encode(r#"{"alg":"none"}"#), padded)).unwrap();
+        assert_eq!(jwt.account_id(), Some("abcdefg"));
+    }
+
+    #[test]
+    fn rejects_malformed_tokens() {
+        let claims = URL_SAFE_NO_PAD.encode(r#"{"sub":"abc"}"#);
+
+        assert!(Jwt::decode("").is_err());
+        assert!(Jwt::decode(&format!("{}.{}", claims, claims)).is_err());
+        assert!(Jwt::decode(&format!("{}.{}.signature.extra", claims, claims)).is_err());
+        assert!(Jwt::decode(&format!("{}.not*base64.signature", claims)).is_err());
+        assert!(Jwt::decode(&format!("{}.{}.signature", claims, URL_SAFE_NO_PAD.encode("[1, 2]"))).is_err());
+    }
+
+    #[test]
+    fn reads_scopes() {
+        let jwt = Jwt::decode(&token(json!({"scope": "basic_profile friends_list"}))).unwrap();
+        assert_eq!(jwt.scopes(), ["basic_profile", "friends_list"]);
+
+        let jwt = Jwt::decode(&token(json!({"scope": ["basic_profile", "openid"]}))).unwrap();
+        assert_eq!(jwt.scopes(), ["basic_profile", "openid"]);
+
+        let jwt = Jwt::decode(&token(json!({}))).unwrap();
+        assert!(jwt.scopes().is_empty());
+    }
+
+    #[test]
+    fn checks_expiration() {
+        let now = Utc::now().timestamp();
+
+        assert!(Jwt::decode(&token(json!({"exp": now - 60}))).unwrap().is_expired());
+        assert!(!Jwt::decode(&token(json!({"exp": now + 3600}))).unwrap().is_expired());
+        assert!(!Jwt::decode(&token(json!({}))).unwrap().is_expired());
+    }
+
+    #[test]
+    fn reads_provider() {
+        let jwt = Jwt::decode(&token(json!({"account_id": "abc", "acProvider": "EasyAntiCheatEOS"}))).unwrap();
+        assert_eq!(jwt.provider(), Some(AntiCheatKind::EasyAntiCheatEOS));
+        assert_eq!(jwt.account_id(), Some("abc"));
+
+        let jwt = Jwt::decode(&token(json!({"acProvider": "NewAntiCheat"}))).unwrap();
+        assert_eq!(jwt.provider(), Some(AntiCheatKind::Unknown(String::from("NewAntiCheat"))));
+
+        assert_eq!(Jwt::decode(&token(json!({}))).unwrap().provider(), None);
+    }
+}
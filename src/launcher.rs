@@ -1,11 +1,12 @@
 use std::{
+    collections::HashMap,
     ffi::{CString, OsString},
     os::windows::prelude::OsStringExt,
     path::PathBuf,
     process::{Child, Command},
 };
 
-use crate::epic::{AntiCheatProvider, ExchangeCode, HasIdentity, HasToken};
+use crate::{arguments::{self, LaunchArguments}, epic::{AntiCheatKind, AntiCheatProvider, ExchangeCode, HasIdentity, HasToken, BATTL_EYE, EASY_ANTI_CHEAT, EASY_ANTI_CHEAT_EOS}, error::LauncherError};
 use epic_manifest_parser_rs::manifest::{FManifest, FManifestParser};
 use windows::{core::PSTR, Win32::{Foundation::CloseHandle, Storage::FileSystem::GetLogicalDriveStringsW, System::Threading::{CreateProcessA, CREATE_SUSPENDED, NORMAL_PRIORITY_CLASS, PROCESS_INFORMATION, STARTUPINFOA}}};
 
@@ -39,7 +40,72 @@ pub struct InstallEntry {
     pub app_name: String,
 }
 
-pub fn get_launcher_installed() -> Result<LauncherInstalled, Box<dyn std::error::Error>> {
+/// How the game is started with an anti cheat.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+pub struct AntiCheatBinary {
+    /// Executable started next to the game, in FortniteGame/Binaries/Win64.
+    pub executable: String,
+    /// Value of the -fromfl argument.
+    pub fromfl: String,
+    /// Other arguments given to the game, without the leading dash, usually disabling the other anti cheats.
+    pub flags: Vec<String>,
+}
+
+impl AntiCheatBinary {
+    pub fn new(executable: &str, fromfl: &str, flags: &[&str]) -> Self {
+        Self {
+            executable: executable.to_string(),
+            fromfl: fromfl.to_string(),
+            flags: flags.iter().map(|flag| flag.to_string()).collect(),
+        }
+    }
+}
+
+/// Anti cheat binaries known by the launcher, by provider name. Providers can be added or overridden
+/// from the configuration.
+#[derive(Clone, Debug)]
+pub struct AntiCheatRegistry {
+    binaries: HashMap<String, AntiCheatBinary>,
+}
+
+impl Default for AntiCheatRegistry {
+    fn default() -> Self {
+        let mut binaries = HashMap::new();
+        binaries.insert(
+            EASY_ANTI_CHEAT.to_string(),
+            AntiCheatBinary::new("FortniteClient-Win64-Shipping_EAC.exe", "eac", &["noeaceos", "nobe"]),
+        );
+        binaries.insert(
+            EASY_ANTI_CHEAT_EOS.to_string(),
+            AntiCheatBinary::new("FortniteClient-Win64-Shipping_EAC_EOS.exe", "eaceos", &["noeac", "nobe"]),
+        );
+        binaries.insert(
+            BATTL_EYE.to_string(),
+            AntiCheatBinary::new("FortniteClient-Win64-Shipping_BE.exe", "be", &["noeac", "noeaceos"]),
+        );
+
+        Self { binaries }
+    }
+}
+
+impl AntiCheatRegistry {
+    /// Built-in binaries, extended or overridden by `overrides`.
+    pub fn with_overrides(overrides: &HashMap<String, AntiCheatBinary>) -> Self {
+        let mut registry = Self::default();
+        registry.binaries.extend(overrides.iter().map(|(name, binary)| (name.clone(), binary.clone())));
+
+        registry
+    }
+
+    pub fn get(&self, kind: &AntiCheatKind) -> Result<&AntiCheatBinary, LauncherError> {
+        self.binaries.get(kind.as_str()).ok_or_else(|| LauncherError::Config {
+            message: format!("Unknown anti cheat provider {}, add it to the anti_cheats of config.json", kind),
+            source: None,
+        })
+    }
+}
+
+pub fn get_launcher_installed() -> Result<LauncherInstalled, LauncherError> {
     let mut buffer: [u16; 255] = unsafe { std::mem::zeroed() };
 
     let len = unsafe { GetLogicalDriveStringsW(Some(&mut buffer)) };
@@ -65,83 +131,49 @@ pub fn get_launcher_installed() -> Result<LauncherInstalled, Box<dyn std::error:
         .next();
 
     if let Some(launcher_installed_path) = result {
-        let content = std::fs::read_to_string(launcher_installed_path)?;
+        let content = std::fs::read_to_string(&launcher_installed_path)
+            .map_err(|err| LauncherError::install(format!("Failed to read {}", launcher_installed_path.display()), err))?;
 
-        return Ok(serde_json::from_str::<LauncherInstalled>(&content)?);
+        serde_json::from_str::<LauncherInstalled>(&content)
+            .map_err(|err| LauncherError::install(format!("Failed to parse {}", launcher_installed_path.display()), err))
     } else {
-        return Err("Installation Path not found".into());
+        Err(LauncherError::Install { message: String::from("Installation Path not found"), source: None })
     }
 }
 
-pub fn generate_arguments<'a, T>(
-    details: &'a T,
-    exchange_code: &'a ExchangeCode,
-    caldera: &'a AntiCheatProvider,
+pub fn generate_arguments<T>(
+    details: &T,
+    exchange_code: &ExchangeCode,
+    caldera: &AntiCheatProvider,
+    anti_cheat: &AntiCheatBinary,
     start_command: Option<&String>,
-) -> Vec<String>
+) -> LaunchArguments
 where
     T: HasIdentity + HasToken,
 {
-    let mut params: Vec<(&str, Option<&str>)> = Vec::new();
-
-    params.push(("AUTH_LOGIN", Some("unused")));
-    params.push(("AUTH_PASSWORD", Some(&exchange_code.code)));
-    params.push(("AUTH_TYPE", Some("exchangecode")));
-    params.push(("epicapp", Some("Fortnite")));
-    params.push(("epicenv", Some("Prod")));
-    params.push(("EpicPortal", None));
-    params.push(("epicusername", Some(details.get_display_name())));
-    params.push(("epicuserid", Some(details.get_account_id())));
-    params.push(("epiclocale", Some("en")));
-    params.push(("epicsandboxid", Some("fn")));
-    match caldera.provider.as_str() {
-        "BattlEye" => {
-            params.push(("noeac", None));
-            params.push(("noeaceos", None));
-            params.push(("fromfl", Some("be")));
-        }
-        "EasyAntiCheatEOS" => {
-            params.push(("noeac", None));
-            params.push(("nobe", None));
-            params.push(("fromfl", Some("eaceos")));
-        }
-        "EasyAntiCheat" => {
-            params.push(("noeaceos", None));
-            params.push(("nobe", None));
-            params.push(("fromfl", Some("eac")));
-        }
-        _ => todo!(),
+    let mut arguments = LaunchArguments::new()
+        .value("AUTH_LOGIN", "unused")
+        .value("AUTH_PASSWORD", &exchange_code.code)
+        .value("AUTH_TYPE", "exchangecode")
+        .value("epicapp", "Fortnite")
+        .value("epicenv", "Prod")
+        .flag("EpicPortal")
+        .value("epicusername", details.get_display_name())
+        .value("epicuserid", details.get_account_id())
+        .value("epiclocale", &arguments::system_locale().unwrap_or_else(|| String::from("en")))
+        .value("epicsandboxid", "fn");
+
+    for flag in &anti_cheat.flags {
+        arguments.set(flag, None);
     }
-    params.push(("caldera", Some(&caldera.jwt)));
-
-    // let _result = params
-    //     .iter()
-    //     .map(|arg| {
-    //         if let Some(value) = arg.1 {
-    //             format!("-{}={}", arg.0, value)
-    //         } else {
-    //             format!("-{}", arg.0)
-    //         }
-    //     })
-    //     .collect::<Vec<String>>()
-    //     .join(" ");
-
-        let mut result = params
-        .iter()
-        .map(|arg| {
-            if let Some(value) = arg.1 {
-                format!("-{}={}", arg.0, value)
-            } else {
-                format!("-{}", arg.0)
-            }
-        })
-        .collect::<Vec<String>>();
+    arguments.set("fromfl", Some(&anti_cheat.fromfl));
+    arguments.set("caldera", Some(&caldera.jwt));
 
-    if start_command.is_some() {
-        result.push(start_command.unwrap().clone());
+    if let Some(start_command) = start_command {
+        arguments.merge(LaunchArguments::parse(start_command));
     }
 
-    result
+    arguments
 }
 
 // pub unsafe fn suspend_process(pid: u32) -> Result<(), Box<dyn std::error::Error>> {
@@ -179,15 +211,15 @@ where
 
 pub fn spawn_child(
     path: &str,
-    arguments: Option<Vec<String>>,
-) -> Result<Child, Box<dyn std::error::Error>> {
-    let mut command = Command::new(&path);
+    arguments: Option<&LaunchArguments>,
+) -> Result<Child, LauncherError> {
+    let mut command = Command::new(path);
 
     if let Some(args) = arguments {
-        command.args(args);
+        command.args(args.to_args());
     }
 
-    let child = command.spawn()?;
+    let child = command.spawn().map_err(|err| LauncherError::process(path, err))?;
 
     Ok(child)
 }
@@ -196,14 +228,14 @@ pub fn create_process(
     path: &str,
     arguments: Option<String>,
     spawn_suspended: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), LauncherError> {
 
     let args = match arguments {
         Some(data) => format!("\"{}\" {}", path, data),
         None => format!("\"{}\"", path)
     };
 
-    let c_arguments = CString::new(args.clone())?;
+    let c_arguments = CString::new(args.clone()).map_err(|err| LauncherError::process(path, err))?;
 
     let mut creation_flags = NORMAL_PRIORITY_CLASS;
 
@@ -211,12 +243,12 @@ pub fn create_process(
         creation_flags = creation_flags | CREATE_SUSPENDED;
     }
 
-    let result: Result<(), Box<dyn std::error::Error>> = unsafe {
+    unsafe {
         let mut startup_info: STARTUPINFOA = std::mem::zeroed();
         let mut process_info: PROCESS_INFORMATION = std::mem::zeroed();
 
 
-        if CreateProcessA(
+        CreateProcessA(
             None,
             PSTR::from_raw(c_arguments.as_ptr() as *mut u8),
             None,
@@ -227,57 +259,63 @@ pub fn create_process(
             None,
             &mut startup_info as *mut STARTUPINFOA,
             &mut process_info,
-        ).is_ok()
-        {
-            CloseHandle(process_info.hThread);
-            CloseHandle(process_info.hProcess);
-
-            Ok(())
-        } else {
-            eprintln!("Path : {}", args);
-            Err("Windows API Error".into())
-        }
-    };
+        )
+        .map_err(|err| LauncherError::process(path, err))?;
+
+        let _ = CloseHandle(process_info.hThread);
+        let _ = CloseHandle(process_info.hProcess);
+    }
 
-    Ok(result?)
+    Ok(())
 }
 
-pub fn find_first_fortnite_manifest(paths:&[PathBuf]) -> Option<FManifest> {
-    paths.iter().find_map(|path| {
-        let manifest_data = std::fs::read(&path).unwrap();
+/// Returns the first Fortnite manifest of `paths`. Manifests which can't be parsed are skipped,
+/// the parsing error is only returned when no manifest has been found.
+pub fn find_first_fortnite_manifest(paths:&[PathBuf]) -> Result<Option<FManifest>, LauncherError> {
+    let mut parse_error = None;
+
+    for path in paths {
+        let manifest_data = std::fs::read(path)
+            .map_err(|err| LauncherError::install(format!("Failed to read {}", path.display()), err))?;
         let mut parser = FManifestParser::new(&manifest_data);
 
-        let manifest = parser.parse();
-        if manifest.is_err() {
-            return None;
-        }
+        let manifest = match parser.parse() {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                parse_error = Some(LauncherError::Manifest { path: path.clone(), source: err });
+                continue;
+            }
+        };
 
-        let manifest = manifest.unwrap();
-        
         if manifest.meta.app_name() == "FortniteReleaseBuilds" {
-            return None;
+            continue;
         }
 
-        Some(manifest)
-    })
+        return Ok(Some(manifest));
+    }
+
+    match parse_error {
+        Some(err) => Err(err),
+        None => Ok(None),
+    }
 }
 
-pub fn find_start_command(fortnite_path:&PathBuf) -> Option<String> {
+pub fn find_start_command(fortnite_path:&PathBuf) -> Result<Option<String>, LauncherError> {
     if !fortnite_path.exists() {
-        return None;
+        return Ok(None);
     }
 
     //check ,egstore folder
     let egstore_path = PathBuf::from(fortnite_path).join(".egstore");
     if !egstore_path.exists() {
-        return None;
+        return Ok(None);
     }
 
     //get all .manifest files in the folder
-    let mut manifest_files = std::fs::read_dir(egstore_path)
-        .unwrap()
+    let mut manifest_files = std::fs::read_dir(&egstore_path)
+        .map_err(|err| LauncherError::install(format!("Failed to list {}", egstore_path.display()), err))?
         .filter_map(|entry| {
-            let entry = entry.as_ref().unwrap();
+            let entry = entry.ok()?;
             if entry.file_name().to_string_lossy().ends_with(".manifest") {
                 Some(entry.path())
             } else {
@@ -286,13 +324,10 @@ pub fn find_start_command(fortnite_path:&PathBuf) -> Option<String> {
         })
         .collect::<Vec<PathBuf>>();
 
-    manifest_files.sort_by(|a,b| {
-        //sort by modified time
-        a.metadata().unwrap().modified().unwrap().cmp(&b.metadata().unwrap().modified().unwrap())
-    });
+    //sort by modified time
+    manifest_files.sort_by_key(|path| path.metadata().and_then(|metadata| metadata.modified()).ok());
 
     let manifest = find_first_fortnite_manifest(&manifest_files)?;
 
-
-    Some(manifest.meta.launch_command().to_string())
-}
\ No newline at end of file
+    Ok(manifest.map(|manifest| manifest.meta.launch_command().to_string()))
+}
@@ -0,0 +1,24 @@
+//! Login into epic accounts and start Fortnite without the Epic Games Launcher.
+//!
+//! [`Launcher`] covers the whole flow, the modules it is built on are public for the callers
+//! which need finer control.
+
+pub mod arguments;
+pub mod config;
+pub mod device_code;
+pub mod epic;
+pub mod error;
+pub mod import;
+mod facade;
+pub mod jwt;
+pub mod launcher;
+pub mod logging;
+mod migrations;
+pub mod redact;
+pub mod rest;
+pub mod secrets;
+pub mod session;
+mod windows;
+
+pub use error::LauncherError;
+pub use facade::{Install, LaunchPlan, Launcher, PlannedProcess};
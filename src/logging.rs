@@ -0,0 +1,92 @@
+use std::{io::Write, path::Path};
+
+use tracing::level_filters::LevelFilter;
+use tracing_appender::{non_blocking::WorkerGuard, rolling};
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, util::SubscriberInitExt, Layer};
+
+use crate::{error::LauncherError, redact};
+
+/// Number of daily log files kept in the log folder.
+const MAX_LOG_FILES: usize = 7;
+
+/// Writer masking the secrets of everything written through it, see `redact::redact`.
+pub struct RedactingWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        //events are formatted before being written in one go, so a secret is never split between two writes
+        let text = String::from_utf8_lossy(buf);
+        self.inner.write_all(redact::redact(&text).as_bytes())?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Wraps the writers made by `M` into `RedactingWriter`s.
+pub struct Redacting<M> {
+    inner: M,
+}
+
+impl<M> Redacting<M> {
+    pub fn new(inner: M) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter {
+            inner: self.inner.make_writer(),
+        }
+    }
+}
+
+/// Level printed on the console : warnings by default, then info, debug and trace with each `-v`.
+pub fn console_level(verbosity: u8) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+/// Logs to the console and to a daily rotated file in `log_folder`, both redacted.
+/// The returned guard flushes the log file when dropped, keep it alive until the end of `main`.
+pub fn init(verbosity: u8, log_folder: &Path) -> Result<WorkerGuard, LauncherError> {
+    let file_appender = rolling::Builder::new()
+        .rotation(rolling::Rotation::DAILY)
+        .filename_prefix("launcher")
+        .filename_suffix("log")
+        .max_log_files(MAX_LOG_FILES)
+        .build(log_folder)
+        .map_err(|err| LauncherError::config(format!("Failed to open the log folder {}", log_folder.display()), err))?;
+    let (file_writer, guard) = tracing_appender::non_blocking(file_appender);
+
+    let console = tracing_subscriber::fmt::layer()
+        .with_writer(Redacting::new(std::io::stderr))
+        .with_target(false)
+        .without_time()
+        .with_filter(console_level(verbosity));
+
+    let file = tracing_subscriber::fmt::layer()
+        .with_writer(Redacting::new(file_writer))
+        .with_ansi(false)
+        .with_filter(if verbosity >= 3 { LevelFilter::TRACE } else { LevelFilter::DEBUG });
+
+    tracing_subscriber::registry()
+        .with(console)
+        .with(file)
+        .try_init()
+        .map_err(|err| LauncherError::config("Failed to initialize the logs", err))?;
+
+    Ok(guard)
+}
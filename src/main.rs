@@ -1,202 +1,505 @@
-use std::{io::Write, path::{Path, PathBuf}, str::FromStr};
-
-use config::Configuration;
-//use egmanifest_rs::Parsable;
-use epic::AccountDetails;
+use std::{error::Error, io::Write, path::Path, process::ExitCode};
+
+use clap::Parser;
+use cli::{AccountsCommand, CalderaArgs, Cli, Command, LaunchArgs};
+use fortnite_launcher::{
+    arguments::LaunchOptions,
+    config::Configuration,
+    epic::{self, AccountDetails, AuthentificationType, CalderaOptions, HasIdentity, HasToken},
+    jwt::Jwt,
+    logging,
+    redact::redact,
+    secrets,
+    session::Session,
+    LaunchPlan, Launcher, LauncherError, PlannedProcess,
+};
 use tokio;
 
-use crate::{
-    epic::{
-        AuthentificationType, HasIdentity, FORTNITE_IOS_GAME_CLIENT,
-        FORTNITE_NEW_SWITCH_GAME_CLIENT, LAUNCHER_APP_CLIENT_2, HasToken,
-    },
-    rest::{EpicError, handle_epic_response},
-};
+mod cli;
 
-mod config;
-mod epic;
-mod launcher;
-mod rest;
-mod windows;
+/// Folder of the log files, next to the configuration.
+const LOG_FOLDER: &str = "logs";
 
-async fn onboarding_authorization_code(
-    configuration: &mut Configuration,
-) -> Result<AccountDetails, Box<dyn std::error::Error>> {
-    print!("\nGet your authorization code here : https://www.epicgames.com/id/api/redirect?clientId={}&responseType=code\nAuthorization code : ", FORTNITE_IOS_GAME_CLIENT.id);
-    std::io::stdout().flush()?;
+fn prompt(message: &str) -> Result<String, LauncherError> {
+    print!("{}", message);
+    std::io::stdout()
+        .flush()
+        .map_err(|err| LauncherError::input("Failed to write to the console", err))?;
 
-    let mut authorization_code = String::new();
+    let mut input = String::new();
+    std::io::stdin()
+        .read_line(&mut input)
+        .map_err(|err| LauncherError::input("Failed to read from the console", err))?;
 
-    std::io::stdin().read_line(&mut authorization_code)?;
+    Ok(input)
+}
 
-    if authorization_code.len() > 32 {
-        authorization_code = authorization_code[0..32].to_string();
-    }
+fn prompt_passphrase(path: &Path, new: bool) -> Result<String, LauncherError> {
+    let read = |message: String| {
+        rpassword::prompt_password(message).map_err(|err| LauncherError::input("Failed to read the passphrase", err))
+    };
 
-    let details =
-        epic::login_with_authorization_code(authorization_code.as_str(), &FORTNITE_IOS_GAME_CLIENT)
-            .await?;
+    if !new {
+        return read(format!("Passphrase of {} : ", path.display()));
+    }
 
-    // let details = epic::exchange_to(&details, &LAUNCHER_APP_CLIENT_2).await?;
+    let passphrase = read(format!("Choose a passphrase for {} : ", path.display()))?;
+    if read(String::from("Confirm the passphrase : "))? != passphrase {
+        return Err(LauncherError::Input {
+            message: String::from("The passphrases don't match"),
+            source: None,
+        });
+    }
 
-    let device_auth = epic::create_device_auth(&details).await?;
+    Ok(passphrase)
+}
 
-    configuration.device_auth = Some(device_auth);
+async fn onboarding_authorization_code(
+    launcher: &mut Launcher,
+    name: Option<&str>,
+    code: Option<String>,
+) -> Result<AccountDetails, LauncherError> {
+    let authorization_code = match code {
+        Some(code) => code,
+        None => {
+            prompt(&format!(
+                "\nGet your authorization code here : {}\nAuthorization code : ",
+                launcher.authorization_code_url()?
+            ))?
+        }
+    };
 
-    Ok(details)
+    launcher.add_account_with_authorization_code(name, &authorization_code).await
 }
 
 async fn onboarding_device_code(
-    configuration: &mut Configuration,
-) -> Result<AccountDetails, Box<dyn std::error::Error>> {
-    let details = epic::client_credentials(&FORTNITE_NEW_SWITCH_GAME_CLIENT).await?;
-    let device_code = epic::create_device_code(&details).await?;
+    launcher: &mut Launcher,
+    name: Option<&str>,
+) -> Result<AccountDetails, LauncherError> {
+    let device_code = launcher.create_device_code().await?;
 
     println!(
         "Please go to {} and enter this code : {} to connect to your epicgames account !",
         device_code.verification_uri, device_code.user_code
     );
 
-    let number_of_intervals = device_code.expires_in / device_code.interval;
+    let details = launcher.add_account_with_device_code(name, device_code, tokio::signal::ctrl_c()).await?;
+    println!("Logged in successfully !");
 
-    for _i in 0..number_of_intervals {
-        std::thread::sleep(std::time::Duration::from_secs(device_code.interval as u64));
+    Ok(details)
+}
 
-        let _ = match epic::login_with_device_code(&device_code, &FORTNITE_NEW_SWITCH_GAME_CLIENT)
-            .await
-        {
-            Ok(details) => {
-                println!("Logged in successfully !");
-                let details = epic::exchange_to(&details, &FORTNITE_IOS_GAME_CLIENT).await?;
-                let device_auth = epic::create_device_auth(&details).await?;
+/// Asks which stored account to use, returns its account id or None if the user wants to add a new one.
+fn pick_account(launcher: &Launcher) -> Result<Option<String>, LauncherError> {
+    let configuration = launcher.configuration();
+    let default_account = configuration.get_default_account().map(|account| account.device_auth.account_id.clone());
+
+    println!();
+    for (i, account) in configuration.accounts.iter().enumerate() {
+        let last_used = account.last_used_label();
+        let is_default = default_account.as_ref() == Some(&account.device_auth.account_id);
+
+        println!(
+            "[{}] {} ({}) - last used : {}{}",
+            i + 1,
+            account.name,
+            account.display_name,
+            last_used,
+            if is_default { " [default]" } else { "" }
+        );
+    }
+    println!("[{}] Add a new account", configuration.accounts.len() + 1);
 
-                configuration.device_auth = Some(device_auth);
+    let choice = prompt("\nWhich account do you want to use (leave empty for the default one) : ")?;
 
-                return Ok(details);
-            }
-            Err(err) => {
-                eprintln!("Error : {}", err);
-            }
+    if choice.trim().is_empty() {
+        return match default_account {
+            Some(account_id) => Ok(Some(account_id)),
+            None => Err(LauncherError::Config { message: String::from("No default account has been set"), source: None }),
         };
     }
 
-    return Err(EpicError::Expired.into());
+    let index: usize = choice
+        .trim()
+        .parse()
+        .map_err(|err| LauncherError::input("Invalid account", err))?;
+
+    match index {
+        i if i >= 1 && i <= configuration.accounts.len() => Ok(Some(configuration.accounts[i - 1].device_auth.account_id.clone())),
+        i if i == configuration.accounts.len() + 1 => Ok(None),
+        _ => Err(LauncherError::Input { message: String::from("Invalid account"), source: None }),
+    }
 }
 
-async fn epic_login(
-    configuration: &mut Configuration,
-) -> Result<AccountDetails, Box<dyn std::error::Error>> {
-    if let Some(device_auth) = &configuration.device_auth {
-        let ios_details =
-            epic::login_with_device_auth(device_auth, &FORTNITE_IOS_GAME_CLIENT).await?;
-        let details = epic::exchange_to(&ios_details, &LAUNCHER_APP_CLIENT_2).await?;
+/// Adds a new account to the configuration, returns its details on the onboarding client.
+async fn onboarding(
+    launcher: &mut Launcher,
+    method: Option<AuthentificationType>,
+    name: Option<&str>,
+    code: Option<String>,
+) -> Result<AccountDetails, LauncherError> {
+    let auth_type = match method {
+        Some(method) => method,
+        None => {
+            let choice = prompt("[1] AuthorizationCode\n[2] DeviceCode\n\nHow do you want to authentificate : ")?;
+
+            choice.parse().map_err(|err: &str| LauncherError::Input { message: err.to_string(), source: None })?
+        }
+    };
 
-        Ok(details)
-    } else {
-        let mut choice: String = String::new();
+    match auth_type {
+        AuthentificationType::AuthorizationCode => onboarding_authorization_code(launcher, name, code).await,
+        AuthentificationType::DeviceCode => onboarding_device_code(launcher, name).await,
+    }
+}
 
-        print!("[1] AuthorizationCode\n[2] DeviceCode\n\nHow do you want to authentificate : ");
-        std::io::stdout().flush()?;
+/// Logs into `account_name`, or into the default account without asking so scripts never block on a prompt.
+/// The picker is only shown with `pick`, or when there is no default account.
+async fn authenticate(launcher: &mut Launcher, account_name: Option<&str>, pick: bool) -> Result<Session, LauncherError> {
+    let default_account = launcher.configuration().get_default_account().map(|account| account.name.clone());
 
-        std::io::stdin().read_line(&mut choice)?;
+    let account = match (account_name, default_account) {
+        (Some(name), _) => Some(name.to_string()),
+        (None, _) if launcher.configuration().accounts.is_empty() => None,
+        (None, Some(default_account)) if !pick => Some(default_account),
+        (None, _) => pick_account(launcher)?,
+    };
 
-        let auth_type: AuthentificationType = choice.parse()?;
+    match account {
+        Some(account) => launcher.authenticate(&account).await,
+        None => {
+            let details = onboarding(launcher, None, None, None).await?;
+            launcher.start_session(details).await
+        }
+    }
+}
 
-        let details = match auth_type {
-            AuthentificationType::AuthorizationCode => {
-                let ios_details = onboarding_authorization_code(configuration).await?;
-                let details = epic::exchange_to(&ios_details, &LAUNCHER_APP_CLIENT_2).await?;
+async fn accounts(launcher: &mut Launcher, command: AccountsCommand) -> Result<(), LauncherError> {
+    let context = launcher.context();
 
-                details
-                //    println!("Access_token : {}", &details.access_token);
-            }
-            AuthentificationType::DeviceCode => {
-                let ios_details = onboarding_device_code(configuration).await?;
-                let details = epic::exchange_to(&ios_details, &LAUNCHER_APP_CLIENT_2).await?;
+    match command {
+        AccountsCommand::Add { name, method, code } => {
+            let details = onboarding(launcher, method, name.as_deref(), code).await?;
+            println!("Added {}", details.get_display_name());
+        }
+        AccountsCommand::List => {
+            let configuration = launcher.configuration();
 
-                details
+            if configuration.accounts.is_empty() {
+                println!("No account has been added yet.");
             }
-        };
 
-        Ok(details)
-    }
-}
+            let default_account = configuration.get_default_account().map(|account| account.device_auth.account_id.clone());
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut configuration = config::Configuration::read()?;
+            for account in &configuration.accounts {
+                let last_used = account.last_used_label();
+                let is_default = default_account.as_ref() == Some(&account.device_auth.account_id);
 
-    let details = epic_login(&mut configuration).await?;
-    println!("Welcome back, {}", details.get_display_name());
+                println!(
+                    "{}{} ({}) - {} - last used : {}",
+                    if is_default { "* " } else { "  " },
+                    account.name,
+                    account.display_name,
+                    account.device_auth.account_id,
+                    last_used
+                );
+            }
+        }
+        AccountsCommand::Remove { account } => {
+            let profile = launcher
+                .configuration_mut()
+                .remove_account(&account)
+                .ok_or_else(|| LauncherError::account_not_found(&account))?;
+
+            launcher.cache_mut().invalidate_account(&profile.device_auth.account_id);
+            println!("Removed {}", profile.name);
+        }
+        AccountsCommand::Default { account } => {
+            let configuration = launcher.configuration_mut();
+            let name = configuration
+                .find_account(&account)
+                .ok_or_else(|| LauncherError::account_not_found(&account))?
+                .name
+                .clone();
+
+            configuration.default_account = Some(name.clone());
+            println!("{} is now the default account", name);
+        }
+        AccountsCommand::Devices { account } => {
+            let (profile, details) = launcher.device_auth_login(account.as_deref()).await?;
+
+            for device_auth in epic::list_device_auths(&context, &details).await? {
+                let created = device_auth.created.and_then(|access| access.date_time).unwrap_or_default();
+                let last_access = device_auth.last_access.and_then(|access| access.date_time).unwrap_or_default();
+                let is_stored = device_auth.device_id == profile.device_auth.device_id;
+
+                println!(
+                    "{}{} - {} - created : {} - last access : {}",
+                    if is_stored { "* " } else { "  " },
+                    device_auth.device_id,
+                    device_auth.user_agent.unwrap_or_default(),
+                    created,
+                    last_access
+                );
+            }
+        }
+        AccountsCommand::Revoke { device_id, account } => {
+            let (profile, details) = launcher.device_auth_login(account.as_deref()).await?;
+
+            if device_id == profile.device_auth.device_id {
+                return Err(LauncherError::Input {
+                    message: String::from("This device auth is the one used by the launcher, use logout or rotate instead"),
+                    source: None,
+                });
+            }
 
-    let anti_cheat = epic::request_anti_cheat_provider(&details).await?;
-    println!("AntiCheat Provider : {}", anti_cheat.provider);
+            epic::delete_device_auth(&context, &details, &device_id).await?;
+            println!("Revoked {}", device_id);
+        }
+        AccountsCommand::Rotate { account } => {
+            let device_auth = launcher.rotate_device_auth(account.as_deref()).await?;
+            println!("Rotated device auth, new device id : {}", device_auth.device_id);
+        }
+        AccountsCommand::Import { source, path } => {
+            let mut first_error = None;
+            let mut imported = false;
+
+            for (account_id, outcome) in launcher.import_accounts(source, path.as_deref()).await? {
+                match outcome {
+                    Ok(profile) => {
+                        imported = true;
+                        println!("Imported {} ({}) from {}", profile.name, profile.display_name, source);
+                    }
+                    Err(err) => {
+                        eprintln!("Failed to import {} : {}", account_id, redact(&err.to_string()));
+                        first_error.get_or_insert(err);
+                    }
+                }
+            }
 
-    if configuration.fortnite_path.is_none()
-        || !PathBuf::from(configuration.fortnite_path.clone().unwrap()).exists()
-    {
-        if let Some(entry) = launcher::get_launcher_installed()?.find("Fortnite") {
-            configuration.fortnite_path = Some(entry.install_location.clone());
-        } else {
-            return Err("No Fortnite Path has been found, please fill it in config.json.".into());
+            if let (false, Some(err)) = (imported, first_error) {
+                return Err(err);
+            }
         }
     }
 
-    let exchange_code = epic::exchange_code(&details).await?;
-    if cfg!(debug_assertions) {
-        println!("Created exchange code successfully : {}", &exchange_code.code);
+    Ok(())
+}
+
+fn print_jwt(jwt: &Jwt) {
+    let date = |date: Option<chrono::DateTime<chrono::Utc>>| date.map(|date| date.to_string()).unwrap_or_else(|| String::from("unknown"));
+
+    println!("Algorithm : {}", jwt.algorithm().unwrap_or("unknown"));
+    if let Some(account_id) = jwt.account_id() {
+        println!("Account id : {}", account_id);
+    }
+    if let Some(display_name) = jwt.display_name() {
+        println!("Display name : {}", display_name);
     }
+    if let Some(client_id) = jwt.client_id() {
+        println!("Client id : {}", client_id);
+    }
+    if let Some(provider) = jwt.provider() {
+        println!("Provider : {}", provider);
+    }
+    let scopes = jwt.scopes();
+    if !scopes.is_empty() {
+        println!("Scopes : {}", scopes.join(" "));
+    }
+    println!("Issued at : {}", date(jwt.issued_at()));
+    println!("Expires at : {}{}", date(jwt.expires_at()), if jwt.is_expired() { " (expired)" } else { "" });
+    println!(
+        "Claims : {}",
+        serde_json::to_string_pretty(&jwt.claims).unwrap_or_default()
+    );
+}
 
-    let response = handle_epic_response(
-        rest::CLIENT
-        .get(format!("https://account-public-service-prod.ol.epicgames.com/account/api/public/account/{}", details.get_account_id()))
-        .bearer_auth(details.get_access_token().token)
-        .send()
-        .await?
-    ).await?;
+async fn inspect_token(
+    launcher: &mut Launcher,
+    token: Option<String>,
+    account_name: Option<&str>,
+    caldera: bool,
+    caldera_options: &CalderaOptions,
+    pick: bool,
+) -> Result<(), LauncherError> {
+    let token = match token {
+        Some(token) => token,
+        None => {
+            let mut session = authenticate(launcher, account_name, pick).await?;
+
+            if caldera {
+                launcher.request_anti_cheat_provider(&mut session, caldera_options).await?.jwt
+            } else {
+                session.get_access_token().token
+            }
+        }
+    };
+
+    print_jwt(&Jwt::decode(&token)?);
 
-    println!("Data : {}", response.text().await?);
+    Ok(())
+}
 
+fn print_process(index: usize, process: &PlannedProcess) {
+    println!(
+        "[{}] {}{}{}",
+        index,
+        process.path.display(),
+        if process.suspended { " (suspended)" } else { "" },
+        if process.running { " - already running, skipped" } else { "" }
+    );
+}
 
-    let start_command = launcher::find_start_command(&PathBuf::from_str(configuration.fortnite_path.as_ref().unwrap()).unwrap());
-    let arguments = launcher::generate_arguments(&details, &exchange_code, &anti_cheat, start_command.as_ref());
+fn print_plan(plan: &LaunchPlan, show_secrets: bool) {
+    println!("Install : {}", plan.install.display());
+    println!("Start command : {}", plan.start_command.as_deref().unwrap_or("none"));
+    println!("Anti cheat : {} ({})", plan.provider, plan.anti_cheat.executable);
 
-    let fortnite_binary_folder = std::path::PathBuf::from(configuration.fortnite_path.clone().unwrap()).join("FortniteGame/Binaries/Win64");
-    if !fortnite_binary_folder.exists()
-    {
-        return Err("Fortnite Binary does not exists !".into());
+    println!("\nProcesses :");
+    for (i, helper) in plan.helpers.iter().enumerate() {
+        print_process(i + 1, helper);
     }
+    print_process(plan.helpers.len() + 1, &plan.game);
 
-    let fortnite_launcher_path_buf = fortnite_binary_folder.join("FortniteLauncher.exe");
-    let fortnite_binary_path_buf = fortnite_binary_folder.join("FortniteClient-Win64-Shipping.exe");
-    let fortnite_anticheat_name = match anti_cheat.provider.as_str() {
-        "EasyAntiCheat" => "FortniteClient-Win64-Shipping_EAC.exe",
-        "EasyAntiCheatEOS" => "FortniteClient-Win64-Shipping_EAC_EOS.exe",
-        "BattlEye" => "FortniteClient-Win64-Shipping_BE.exe",
-        _ => todo!()
-    };
-    let fortnite_anticheat_path_buf = fortnite_binary_folder.join(fortnite_anticheat_name);
+    if let Some(arguments) = &plan.game.arguments {
+        let arguments = if show_secrets { arguments.clone() } else { arguments.redacted() };
 
-    if unsafe {
-        windows::find_process("FortniteLauncher.exe")
-    }.is_none() {
-        launcher::create_process(fortnite_launcher_path_buf.to_str().ok_or("Failed to str")?, None, true)?;
+        println!("\nArguments :");
+        for argument in arguments.to_args() {
+            println!("  {}", argument);
+        }
     }
+}
 
-    if unsafe {
-        windows::find_process(fortnite_anticheat_name)
-    }.is_none() {
-        launcher::create_process(fortnite_anticheat_path_buf.to_str().ok_or("Failed to str")?,  None, true)?;
-    }
+async fn launch(
+    launcher: &mut Launcher,
+    account_name: Option<&str>,
+    caldera_options: &CalderaOptions,
+    launch_options: &LaunchOptions,
+    dry_run: bool,
+    show_secrets: bool,
+    pick: bool,
+) -> Result<(), LauncherError> {
+    let mut session = authenticate(launcher, account_name, pick).await?;
+    println!("Welcome back, {}", session.get_display_name());
+
+    let anti_cheat = launcher.request_anti_cheat_provider(&mut session, caldera_options).await?;
+    println!("AntiCheat Provider : {}", anti_cheat.provider);
 
-    let fortnite_process = launcher::spawn_child(fortnite_binary_path_buf.to_str().ok_or("Failed to str")?, Some(arguments))?;
+    let install = launcher.resolve_install()?;
+    let arguments = launcher.build_arguments(&mut session, &install, &anti_cheat, launch_options).await?;
 
-    if cfg!(debug_assertions)
-    {
-        println!("Created Fortnite Process, PID : {}", fortnite_process.id());
+    if dry_run {
+        print_plan(&launcher.plan(&install, &anti_cheat, &arguments)?, show_secrets);
+        return Ok(());
     }
 
+    launcher.spawn(&install, &anti_cheat, &arguments)?;
+
     std::thread::sleep(std::time::Duration::from_secs(10));
 
     Ok(())
 }
+
+async fn run(cli: Cli) -> Result<(), LauncherError> {
+    let mut launcher = Launcher::open(cli.config.as_deref()).await?;
+
+    //a failed command may still have changed the configuration, like a rotated device auth
+    let result = execute(&mut launcher, cli.command, cli.pick).await;
+    let saved = launcher.save();
+
+    match result {
+        Err(err) => {
+            if let Err(save_err) = saved {
+                tracing::error!("Failed to save the configuration : {}", save_err);
+            }
+            Err(err)
+        }
+        Ok(()) => saved,
+    }
+}
+
+async fn execute(launcher: &mut Launcher, command: Option<Command>, pick: bool) -> Result<(), LauncherError> {
+    let command = command.unwrap_or(Command::Launch {
+        account: None,
+        caldera_options: CalderaArgs::default(),
+        launch_options: LaunchArgs::default(),
+        dry_run: false,
+        show_secrets: false,
+    });
+
+    match command {
+        Command::Accounts(command) => accounts(launcher, command).await?,
+        Command::Login { account } => {
+            let session = authenticate(launcher, account.as_deref(), pick).await?;
+            println!("Logged in as {}", session.get_display_name());
+        }
+        Command::Logout { account, everywhere } => {
+            let profile = launcher.logout(account.as_deref(), everywhere).await?;
+            println!("Logged out {}", profile.name);
+        }
+        Command::Whoami { account } => {
+            let mut session = authenticate(launcher, account.as_deref(), pick).await?;
+            let verification = launcher.verify(&mut session).await?;
+
+            println!("Display name : {}", verification.display_name.unwrap_or_default());
+            println!("Account id : {}", verification.account_id.unwrap_or_default());
+            println!("Client id : {} ({})", verification.client_id, verification.client_service);
+            println!("Auth method : {}", verification.auth_method);
+            println!("Expires at : {}", verification.expires_at);
+            if !verification.scope.is_empty() {
+                println!("Scopes : {}", verification.scope.join(" "));
+            }
+        }
+        Command::InspectToken { token, account, caldera, caldera_options } => {
+            inspect_token(launcher, token, account.as_deref(), caldera, &caldera_options.into(), pick).await?
+        }
+        Command::Launch { account, caldera_options, launch_options, dry_run, show_secrets } => {
+            launch(
+                launcher,
+                account.as_deref(),
+                &caldera_options.into(),
+                &launch_options.into(),
+                dry_run,
+                show_secrets,
+                pick,
+            )
+            .await?
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    //keeps the log file open until the end of main
+    let log_folder = Configuration::locate(cli.config.as_deref()).with_file_name(LOG_FOLDER);
+    let _guard = match logging::init(cli.verbose, &log_folder) {
+        Ok(guard) => Some(guard),
+        Err(err) => {
+            eprintln!("Error : {}", err);
+            None
+        }
+    };
+
+    secrets::set_passphrase_prompt(prompt_passphrase);
+
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            tracing::debug!(error = ?err, "Exiting with an error");
+            eprintln!("Error : {}", redact(&err.to_string()));
+
+            let mut source = err.source();
+            while let Some(cause) = source {
+                eprintln!("Caused by : {}", redact(&cause.to_string()));
+                source = cause.source();
+            }
+
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
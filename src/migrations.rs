@@ -0,0 +1,130 @@
+use serde_json::{json, Map, Value};
+
+use crate::error::LauncherError;
+
+/// Version of the configuration written by this build.
+pub const CURRENT_VERSION: u64 = 1;
+
+type Migration = fn(&mut Map<String, Value>) -> Result<(), LauncherError>;
+
+/// `MIGRATIONS[n]` upgrades a configuration from version `n` to version `n + 1`.
+const MIGRATIONS: [Migration; CURRENT_VERSION as usize] = [from_single_account];
+
+/// Version 0 is the flat `{device_auth, fortnite_path}` file, version 1 stores a list of accounts.
+fn from_single_account(configuration: &mut Map<String, Value>) -> Result<(), LauncherError> {
+    let Some(device_auth) = configuration.remove("device_auth").filter(|device_auth| !device_auth.is_null()) else {
+        return Ok(());
+    };
+
+    let account_id = device_auth
+        .get("accountId")
+        .and_then(Value::as_str)
+        .ok_or_else(|| LauncherError::Config {
+            message: String::from("The device auth of the configuration has no account id"),
+            source: None,
+        })?
+        .to_string();
+
+    let accounts = configuration.entry("accounts").or_insert_with(|| json!([]));
+    if let Some(accounts) = accounts.as_array_mut() {
+        accounts.push(json!({
+            "name": account_id,
+            "display_name": account_id,
+            "device_auth": device_auth,
+            "last_used": null,
+        }));
+    }
+
+    configuration
+        .entry("default_account")
+        .and_modify(|default_account| {
+            if default_account.is_null() {
+                *default_account = json!(account_id);
+            }
+        })
+        .or_insert_with(|| json!(account_id));
+
+    Ok(())
+}
+
+/// Upgrades a configuration of any older version to `CURRENT_VERSION`, returns true if it has been changed.
+/// Configurations without a version are version 0.
+pub fn migrate(configuration: &mut Value) -> Result<bool, LauncherError> {
+    let Some(configuration) = configuration.as_object_mut() else {
+        return Err(LauncherError::Config {
+            message: String::from("The configuration is not a JSON object"),
+            source: None,
+        });
+    };
+
+    let version = configuration.get("version").and_then(Value::as_u64).unwrap_or(0);
+
+    if version > CURRENT_VERSION {
+        return Err(LauncherError::Config {
+            message: format!(
+                "The configuration has been written by a newer version of the launcher (version {}, this build supports up to {})",
+                version, CURRENT_VERSION
+            ),
+            source: None,
+        });
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        tracing::info!(from, to = from + 1, "Migrating the configuration");
+        migration(configuration)?;
+    }
+
+    configuration.insert(String::from("version"), json!(CURRENT_VERSION));
+
+    Ok(version != CURRENT_VERSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_single_account() {
+        let mut configuration = json!({
+            "device_auth": {"accountId": "abc", "deviceId": "device", "secret": "secret"},
+            "fortnite_path": "C:\\Fortnite",
+        });
+
+        assert!(migrate(&mut configuration).unwrap());
+        assert_eq!(configuration["version"], json!(CURRENT_VERSION));
+        assert_eq!(configuration["default_account"], json!("abc"));
+        assert_eq!(configuration["fortnite_path"], json!("C:\\Fortnite"));
+        assert!(configuration.get("device_auth").is_none());
+
+        let accounts = configuration["accounts"].as_array().unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0]["name"], json!("abc"));
+        assert_eq!(accounts[0]["device_auth"]["deviceId"], json!("device"));
+    }
+
+    #[test]
+    fn migrates_empty_configuration() {
+        let mut configuration = json!({"device_auth": null});
+
+        assert!(migrate(&mut configuration).unwrap());
+        assert_eq!(configuration["version"], json!(CURRENT_VERSION));
+        assert!(configuration.get("accounts").is_none());
+    }
+
+    #[test]
+    fn keeps_current_configuration() {
+        let mut configuration = json!({"version": CURRENT_VERSION, "accounts": []});
+
+        assert!(!migrate(&mut configuration).unwrap());
+        assert_eq!(configuration, json!({"version": CURRENT_VERSION, "accounts": []}));
+    }
+
+    #[test]
+    fn rejects_newer_configuration() {
+        let mut configuration = json!({"version": CURRENT_VERSION + 1});
+        assert!(migrate(&mut configuration).is_err());
+
+        assert!(migrate(&mut json!([])).is_err());
+        assert!(migrate(&mut json!({"device_auth": {"deviceId": "device"}})).is_err());
+    }
+}
@@ -1,59 +1,398 @@
-use reqwest::Response;
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use reqwest::{Request, RequestBuilder, Response};
 use lazy_static::lazy_static;
 
+use crate::error::LauncherError;
+
 lazy_static! {
     pub static ref CLIENT:reqwest::Client = {
         reqwest::Client::builder().build().unwrap()
     };
 }
 
+/// Base urls of the epic services, they can be pointed to a local server to test the launcher offline.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EpicEndpoints {
+    pub account: String,
+    pub caldera: String,
+    pub website: String,
+}
+
+impl Default for EpicEndpoints {
+    fn default() -> Self {
+        Self {
+            account: String::from("https://account-public-service-prod.ol.epicgames.com"),
+            caldera: String::from("https://caldera-service-prod.ecosec.on.epicgames.com"),
+            website: String::from("https://www.epicgames.com"),
+        }
+    }
+}
+
+fn join(base: &str, path: &str) -> String {
+    format!("{}/{}", base.trim_end_matches('/'), path.trim_start_matches('/'))
+}
+
+impl EpicEndpoints {
+    pub fn account(&self, path: &str) -> String {
+        join(&self.account, path)
+    }
+
+    pub fn caldera(&self, path: &str) -> String {
+        join(&self.caldera, path)
+    }
+
+    pub fn website(&self, path: &str) -> String {
+        join(&self.website, path)
+    }
+}
+
+/// How failed requests are retried. Delays are in milliseconds.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// Retry on 5xx and connection errors, the request may already have been processed by epic.
+    pub retry_server_errors: bool,
+    /// Retry on 429, the request has been rejected before being processed.
+    pub retry_rate_limits: bool,
+    /// Changes to the policy for the requests whose path starts with the key, the longest matching key wins.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub endpoints: HashMap<String, RetryOverride>,
+}
+
+/// Fields of the retry policy changed for some endpoints, the missing ones are kept.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RetryOverride {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_delay_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_delay_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_server_errors: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_rate_limits: Option<bool>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay_ms: 500,
+            max_delay_ms: 30_000,
+            retry_server_errors: true,
+            retry_rate_limits: true,
+            endpoints: HashMap::new(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Policy of the requests sent to `path`, with the override of the longest matching endpoint applied.
+    pub fn for_path(&self, path: &str) -> Self {
+        let mut policy = self.clone();
+
+        if let Some((_, changes)) = self
+            .endpoints
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+        {
+            policy.max_retries = changes.max_retries.unwrap_or(policy.max_retries);
+            policy.base_delay_ms = changes.base_delay_ms.unwrap_or(policy.base_delay_ms);
+            policy.max_delay_ms = changes.max_delay_ms.unwrap_or(policy.max_delay_ms);
+            policy.retry_server_errors = changes.retry_server_errors.unwrap_or(policy.retry_server_errors);
+            policy.retry_rate_limits = changes.retry_rate_limits.unwrap_or(policy.retry_rate_limits);
+        }
+
+        policy
+    }
+
+    /// Same policy, restricted to what is safe for requests which must not be processed twice,
+    /// such as the creation of an exchange code.
+    pub fn non_idempotent(&self) -> Self {
+        Self {
+            retry_server_errors: false,
+            ..self.clone()
+        }
+    }
+
+    /// Exponential backoff with full jitter.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .base_delay_ms
+            .saturating_mul(1u64 << attempt.min(16))
+            .min(self.max_delay_ms);
+        let jitter = RandomState::new().build_hasher().finish() % (ceiling + 1);
+
+        Duration::from_millis(jitter)
+    }
+}
+
+/// Delay asked by epic, from the `Retry-After` header or the first numeric message var of the error.
+fn retry_after(headers: &reqwest::header::HeaderMap, descriptor: Option<&EpicErrorDescriptor>) -> Option<Duration> {
+    let from_header = headers
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+
+    let from_message_vars = descriptor
+        .and_then(|data| data.message_vars.as_ref())
+        .and_then(|vars| vars.iter().find_map(|var| var.trim().parse::<u64>().ok()));
+
+    from_header.or(from_message_vars).map(Duration::from_secs)
+}
+
+/// What every request to epic needs : the http client, where to send the requests and how to retry them.
+#[derive(Debug, Clone)]
+pub struct EpicContext {
+    pub http: reqwest::Client,
+    pub endpoints: EpicEndpoints,
+    pub retry: RetryPolicy,
+}
+
+impl EpicContext {
+    pub fn new(endpoints: EpicEndpoints, retry: RetryPolicy) -> Self {
+        Self {
+            http: CLIENT.clone(),
+            endpoints,
+            retry,
+        }
+    }
+
+    /// Sends an idempotent request, retrying it according to the retry policy of the context.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, LauncherError> {
+        self.send_request(request, false).await
+    }
+
+    /// Sends a request which must not be processed twice, it is only retried when epic rate limited it.
+    /// Endpoint overrides can't make it retry on server errors.
+    pub async fn send_once(&self, request: RequestBuilder) -> Result<Response, LauncherError> {
+        self.send_request(request, true).await
+    }
+
+    async fn send_request(&self, request: RequestBuilder, once: bool) -> Result<Response, LauncherError> {
+        let (client, request) = request.build_split();
+        let request = request?;
+
+        let policy = self.retry.for_path(request.url().path());
+        let policy = if once { policy.non_idempotent() } else { policy };
+
+        Self::execute(&client, request, &policy).await
+    }
+
+    pub async fn send_with_policy(
+        &self,
+        request: RequestBuilder,
+        policy: &RetryPolicy,
+    ) -> Result<Response, LauncherError> {
+        let (client, request) = request.build_split();
+
+        Self::execute(&client, request?, policy).await
+    }
+
+    async fn execute(client: &reqwest::Client, mut request: Request, policy: &RetryPolicy) -> Result<Response, LauncherError> {
+        let mut attempt = 0;
+
+        loop {
+            //requests with a streamed body can't be cloned, they are sent only once
+            let next = request.try_clone().filter(|_| attempt < policy.max_retries);
+
+            let response = match client.execute(request).await {
+                Ok(response) => response,
+                Err(err) => match next {
+                    Some(next) if policy.retry_server_errors && (err.is_connect() || err.is_timeout()) => {
+                        tokio::time::sleep(policy.backoff(attempt)).await;
+                        attempt += 1;
+                        request = next;
+                        continue;
+                    }
+                    _ => return Err(err.into()),
+                },
+            };
+
+            let status = response.status();
+            let retryable = (status.as_u16() == 429 && policy.retry_rate_limits)
+                || (status.is_server_error() && policy.retry_server_errors);
+
+            let Some(next) = next.filter(|_| retryable) else {
+                return Ok(handle_epic_response(response).await?);
+            };
+
+            let headers = response.headers().clone();
+            let descriptor = response.json::<EpicErrorDescriptor>().await.ok();
+
+            let delay = match retry_after(&headers, descriptor.as_ref()) {
+                Some(delay) if delay > Duration::from_millis(policy.max_delay_ms) => {
+                    return Err(EpicError::from_status(status.as_u16(), descriptor).into())
+                }
+                Some(delay) => delay,
+                None => policy.backoff(attempt),
+            };
+
+            tracing::warn!(status = status.as_u16(), delay_ms = delay.as_millis() as u64, attempt, "Request failed, retrying");
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+            request = next;
+        }
+    }
+}
+
+impl Default for EpicContext {
+    fn default() -> Self {
+        Self::new(EpicEndpoints::default(), RetryPolicy::default())
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct EpicErrorDescriptor {
     #[serde(rename = "errorCode")]
     pub error_code: String,
-    #[serde(rename = "errorMessage")]
+    //only the error code is sent by every service
+    #[serde(rename = "errorMessage", default)]
     pub error_message: String,
-    #[serde(rename = "numericErrorCode")]
+    #[serde(rename = "numericErrorCode", default)]
     pub numeric_error_code: i64,
-    #[serde(rename = "originatingService")]
+    #[serde(rename = "originatingService", default)]
     pub originating_service: String,
+    #[serde(default)]
     pub intent: String,
-    pub message_vars:Option<Vec<String>>
+    #[serde(rename = "messageVars", default)]
+    pub message_vars:Option<Vec<String>>,
+    /// OAuth error of the token endpoint, sent next to the epic error code.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
 
 #[derive(Debug)]
 pub enum EpicError {
-    NotFound,
-    RateLimited,
-    Unauthorized,
-    Forbidden,
-    InternalError,
+    NotFound(Option<Box<EpicErrorDescriptor>>),
+    RateLimited(Option<Box<EpicErrorDescriptor>>),
+    Unauthorized(Option<Box<EpicErrorDescriptor>>),
+    Forbidden(Option<Box<EpicErrorDescriptor>>),
+    InternalError(Option<Box<EpicErrorDescriptor>>),
     ClientMismatch,
     Expired,
+    AuthorizationCodeNotFound(Box<EpicErrorDescriptor>),
+    AuthorizationPending(Box<EpicErrorDescriptor>),
+    SlowDown(Box<EpicErrorDescriptor>),
+    DeviceCodeExpired(Box<EpicErrorDescriptor>),
+    InvalidGrant(Box<EpicErrorDescriptor>),
+    TwoFactorRequired(Box<EpicErrorDescriptor>),
+    CorrectiveActionRequired(Box<EpicErrorDescriptor>),
+    AccountNotActive(Box<EpicErrorDescriptor>),
+    ClientDisabled(Box<EpicErrorDescriptor>),
     Other,
-    Unknown(EpicErrorDescriptor)
+    Unknown(Box<EpicErrorDescriptor>)
+}
+
+type ErrorConstructor = fn(Box<EpicErrorDescriptor>) -> EpicError;
+
+/// Known epic error codes, matched on the end of `errorCode` since the same error can be sent by several services.
+const KNOWN_ERRORS: &[(&str, ErrorConstructor)] = &[
+    ("oauth.authorization_code_not_found", EpicError::AuthorizationCodeNotFound),
+    ("authorization_pending", EpicError::AuthorizationPending),
+    ("slow_down", EpicError::SlowDown),
+    ("expired_token", EpicError::DeviceCodeExpired),
+    //sent with a 400 for the device codes which have expired
+    ("errors.com.epicgames.not_found", |data| EpicError::NotFound(Some(data))),
+    ("invalid_grant", EpicError::InvalidGrant),
+    ("auth_token.invalid_refresh_token", EpicError::InvalidGrant),
+    //sent by the device auth login once the device auth has been revoked
+    ("account.invalid_account_credentials", EpicError::InvalidGrant),
+    ("two_factor_authentication.required", EpicError::TwoFactorRequired),
+    ("corrective_action_required", EpicError::CorrectiveActionRequired),
+    ("account_not_active", EpicError::AccountNotActive),
+    ("client_disabled", EpicError::ClientDisabled),
+];
+
+impl EpicError {
+    pub fn descriptor(&self) -> Option<&EpicErrorDescriptor> {
+        match self {
+            EpicError::NotFound(data)
+            | EpicError::RateLimited(data)
+            | EpicError::Unauthorized(data)
+            | EpicError::Forbidden(data)
+            | EpicError::InternalError(data) => data.as_deref(),
+            EpicError::AuthorizationCodeNotFound(data)
+            | EpicError::AuthorizationPending(data)
+            | EpicError::SlowDown(data)
+            | EpicError::DeviceCodeExpired(data)
+            | EpicError::InvalidGrant(data)
+            | EpicError::TwoFactorRequired(data)
+            | EpicError::CorrectiveActionRequired(data)
+            | EpicError::AccountNotActive(data)
+            | EpicError::ClientDisabled(data)
+            | EpicError::Unknown(data) => Some(data),
+            EpicError::ClientMismatch | EpicError::Expired | EpicError::Other => None,
+        }
+    }
+
+    fn from_status(status_code: u16, descriptor: Option<EpicErrorDescriptor>) -> Self {
+        let descriptor = descriptor.map(Box::new);
+
+        if let Some(data) = &descriptor {
+            let known = |code: &&str| data.error_code.ends_with(code) || data.error.as_deref() == Some(code);
+            if let Some((_, variant)) = KNOWN_ERRORS.iter().find(|(code, _)| known(code)) {
+                return variant(data.clone());
+            }
+        }
+
+        match (status_code, descriptor) {
+            (404, data) => EpicError::NotFound(data),
+            (403, data) => EpicError::Forbidden(data),
+            (429, data) => EpicError::RateLimited(data),
+            (401, data) => EpicError::Unauthorized(data),
+            (500..=599, data) => EpicError::InternalError(data),
+            (_, Some(data)) => EpicError::Unknown(data),
+            (_, None) => EpicError::Other,
+        }
+    }
 }
 
 impl std::fmt::Display for EpicError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let _ = match self {
-            EpicError::NotFound => write!(f, "Not Found")?,
-            EpicError::RateLimited => write!(f, "Ratelimited")?,
-            EpicError::Unauthorized => write!(f, "Unauthorized")?,
-            EpicError::Forbidden => write!(f, "Forbidden")?,
-            EpicError::InternalError => write!(f, "Unreachable server.")?,
+        match self {
+            EpicError::NotFound(_) => write!(f, "Not Found")?,
+            EpicError::RateLimited(_) => write!(f, "Ratelimited")?,
+            EpicError::Unauthorized(_) => write!(f, "Unauthorized")?,
+            EpicError::Forbidden(_) => write!(f, "Forbidden")?,
+            EpicError::InternalError(_) => write!(f, "Unreachable server.")?,
             EpicError::ClientMismatch => write!(f, "You are using the wrong client")?,
             EpicError::Other => write!(f, "Internal Error")?,
             EpicError::Expired => write!(f, "Expired")?,
-            EpicError::Unknown(data) => {
-                write!(f, "Error code : {}\nError Messsage : {}\nNumeric error code : {}\nIntent : {}\n", data.error_code, data.error_message, data.numeric_error_code, data.intent)?;
-                if let Some(vars) = &data.message_vars {
-                    write!(f, "Message vars : \n{}\n", vars.join("\n"))?;
-                }
-            }
+            EpicError::AuthorizationCodeNotFound(_) => write!(f, "The authorization code is invalid, has expired or has already been used, please get a new one")?,
+            EpicError::AuthorizationPending(_) => write!(f, "The authorization is still pending")?,
+            EpicError::SlowDown(_) => write!(f, "Too many requests, please slow down")?,
+            EpicError::DeviceCodeExpired(_) => write!(f, "The device code has expired, please start again")?,
+            EpicError::InvalidGrant(_) => write!(f, "The credentials are invalid or have been revoked, please login again")?,
+            EpicError::TwoFactorRequired(_) => write!(f, "Two factor authentication is required for this account")?,
+            EpicError::CorrectiveActionRequired(_) => write!(f, "An action is required on this account, please login on the epicgames website")?,
+            EpicError::AccountNotActive(_) => write!(f, "This account is not active")?,
+            EpicError::ClientDisabled(_) => write!(f, "This client has been disabled by epic, please update the clients of config.json")?,
+            EpicError::Unknown(_) => (),
         };
 
+        if let Some(data) = self.descriptor() {
+            if !matches!(self, EpicError::Unknown(_)) {
+                writeln!(f)?;
+            }
+
+            write!(f, "Error code : {}\nError Messsage : {}\nNumeric error code : {}\nIntent : {}\n", data.error_code, data.error_message, data.numeric_error_code, data.intent)?;
+            if let Some(vars) = &data.message_vars {
+                write!(f, "Message vars : \n{}\n", vars.join("\n"))?;
+            }
+        }
+
         Ok(())
     }
 }
@@ -61,35 +400,129 @@ impl std::fmt::Display for EpicError {
 impl std::error::Error for EpicError {}
 
 pub async fn handle_epic_response(response:Response) -> Result<Response, EpicError> {
-    let status_code = response.status().as_u16();
+    let status = response.status();
 
-    match status_code {
-        404 => return Err(EpicError::NotFound),
-        403 => {
-            println!("Body : {}", response.text().await.unwrap());
-            return Err(EpicError::Forbidden)
-        },
-        429 => return Err(EpicError::RateLimited),
-        401 => return Err(EpicError::Unauthorized),
-        _ => ()
+    if status.is_success() {
+        return Ok(response);
     }
 
-    if response.status().is_server_error() {
-        return Err(EpicError::InternalError);
+    let url = response.url().path().to_string();
+    let descriptor = response.json::<EpicErrorDescriptor>().await.ok();
+
+    tracing::debug!(
+        status = status.as_u16(),
+        url = %url,
+        error_code = descriptor.as_ref().map(|data| data.error_code.as_str()).unwrap_or_default(),
+        "Epic answered with an error"
+    );
+
+    Err(EpicError::from_status(status.as_u16(), descriptor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(json: &str) -> Option<EpicErrorDescriptor> {
+        Some(serde_json::from_str(json).unwrap())
     }
 
-    if !response.status().is_success() {
-        match response.json::<EpicErrorDescriptor>().await {
-            Ok(data) => {
-                return Err(EpicError::Unknown(data))
-            },
-            Err(_err) => {
-                //TODO : parse epic known errors codes
+    #[test]
+    fn parses_partial_descriptor() {
+        let data = descriptor(r#"{"errorCode":"errors.com.epicgames.caldera.invalid_request"}"#).unwrap();
 
-                return Err(EpicError::Other)
-            }
-        }
+        assert_eq!(data.error_code, "errors.com.epicgames.caldera.invalid_request");
+        assert!(data.error_message.is_empty());
+        assert_eq!(data.numeric_error_code, 0);
+    }
+
+    #[test]
+    fn maps_known_codes() {
+        let pending = descriptor(r#"{"errorCode":"errors.com.epicgames.account.oauth.authorization_pending","errorMessage":"Pending"}"#);
+        assert!(matches!(EpicError::from_status(400, pending), EpicError::AuthorizationPending(_)));
+
+        let expired = descriptor(r#"{"errorCode":"errors.com.epicgames.account.oauth.expired_token"}"#);
+        assert!(matches!(EpicError::from_status(400, expired), EpicError::DeviceCodeExpired(_)));
+
+        let not_found = descriptor(r#"{"errorCode":"errors.com.epicgames.not_found"}"#);
+        assert!(matches!(EpicError::from_status(400, not_found), EpicError::NotFound(Some(_))));
+
+        let code = descriptor(r#"{"errorCode":"errors.com.epicgames.account.oauth.authorization_code_not_found"}"#);
+        assert!(matches!(EpicError::from_status(400, code), EpicError::AuthorizationCodeNotFound(_)));
+    }
+
+    #[test]
+    fn maps_revoked_refresh_token() {
+        let revoked = descriptor(r#"{"errorCode":"errors.com.epicgames.account.auth_token.invalid_refresh_token","errorMessage":"Sorry the refresh token is invalid","numericErrorCode":18036,"originatingService":"com.epicgames.account.public","intent":"prod"}"#);
+        let err = LauncherError::from(EpicError::from_status(400, revoked));
+        assert!(matches!(err, LauncherError::Epic(EpicError::InvalidGrant(_))));
+        assert!(err.is_invalid_credentials());
+
+        let oauth = descriptor(r#"{"errorCode":"errors.com.epicgames.account.oauth.some_new_code","error":"invalid_grant"}"#);
+        assert!(LauncherError::from(EpicError::from_status(400, oauth)).is_invalid_credentials());
+    }
+
+    #[test]
+    fn maps_revoked_device_auth() {
+        let revoked = descriptor(r#"{"errorCode":"errors.com.epicgames.account.invalid_account_credentials","errorMessage":"Sorry the account credentials you are using are invalid","numericErrorCode":18031}"#);
+        let err = LauncherError::from(EpicError::from_status(400, revoked));
+
+        assert!(matches!(err, LauncherError::Epic(EpicError::InvalidGrant(_))));
+        assert!(err.is_invalid_credentials());
+    }
+
+    #[test]
+    fn reads_retry_after() {
+        let throttled = descriptor(r#"{"errorCode":"errors.com.epicgames.common.throttled","messageVars":["7"]}"#);
+        let mut headers = reqwest::header::HeaderMap::new();
+
+        assert_eq!(retry_after(&headers, throttled.as_ref()), Some(Duration::from_secs(7)));
+        assert_eq!(retry_after(&headers, None), None);
+
+        headers.insert(reqwest::header::RETRY_AFTER, "3".parse().unwrap());
+        assert_eq!(retry_after(&headers, throttled.as_ref()), Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn applies_longest_endpoint() {
+        let mut policy = RetryPolicy::default();
+        policy.endpoints.insert(String::from("/account/api"), RetryOverride { max_retries: Some(1), ..Default::default() });
+        policy.endpoints.insert(
+            String::from("/account/api/oauth"),
+            RetryOverride { retry_server_errors: Some(false), ..Default::default() },
+        );
+
+        let oauth = policy.for_path("/account/api/oauth/token");
+        assert_eq!(oauth.max_retries, 3);
+        assert!(!oauth.retry_server_errors);
+
+        let account = policy.for_path("/account/api/public/account");
+        assert_eq!(account.max_retries, 1);
+        assert!(account.retry_server_errors);
+
+        assert_eq!(policy.for_path("/caldera/api/v1/launcher/racp"), policy);
+    }
+
+    #[test]
+    fn never_retries_server_errors_of_non_idempotent_requests() {
+        let policy = RetryPolicy::default().non_idempotent();
+
+        assert!(!policy.retry_server_errors);
+        assert!(policy.retry_rate_limits);
+        assert_eq!(policy.max_retries, RetryPolicy::default().max_retries);
+    }
+
+    #[test]
+    fn maps_status_codes() {
+        let unknown = descriptor(r#"{"errorCode":"errors.com.epicgames.common.unknown"}"#);
+
+        assert!(matches!(EpicError::from_status(401, None), EpicError::Unauthorized(None)));
+        assert!(matches!(EpicError::from_status(403, None), EpicError::Forbidden(None)));
+        assert!(matches!(EpicError::from_status(404, None), EpicError::NotFound(None)));
+        assert!(matches!(EpicError::from_status(429, None), EpicError::RateLimited(None)));
+        assert!(matches!(EpicError::from_status(503, None), EpicError::InternalError(None)));
+        assert!(matches!(EpicError::from_status(401, unknown.clone()), EpicError::Unauthorized(Some(_))));
+        assert!(matches!(EpicError::from_status(400, unknown), EpicError::Unknown(_)));
+        assert!(matches!(EpicError::from_status(400, None), EpicError::Other));
     }
-    
-    Ok(response)
 }
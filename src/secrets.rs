@@ -0,0 +1,570 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use lazy_static::lazy_static;
+
+use crate::{config, error::LauncherError, redact};
+
+/// Service name of the launcher entries in the keyring.
+const KEYRING_SERVICE: &str = "FortniteLauncher";
+/// Encrypted secrets, next to the configuration.
+const SECRETS_FILE: &str = "secrets.enc";
+const SALT_LENGTH: usize = 16;
+
+/// Environment variable holding the passphrase of the encrypted file, asked for when it is unset.
+pub const PASSPHRASE_ENV: &str = "FNL_PASSPHRASE";
+
+/// Asks the user for the passphrase of the encrypted file at the given path,
+/// `new` is set when the file doesn't exist yet and the passphrase is being chosen.
+pub type PassphrasePrompt = fn(path: &Path, new: bool) -> Result<String, LauncherError>;
+
+lazy_static! {
+    static ref PASSPHRASE_PROMPT: RwLock<Option<PassphrasePrompt>> = RwLock::new(None);
+}
+
+/// Sets how the passphrase is asked for when `FNL_PASSPHRASE` is unset.
+/// Without a prompt, the encrypted file can only be opened through the environment variable.
+pub fn set_passphrase_prompt(prompt: PassphrasePrompt) {
+    if let Ok(mut current) = PASSPHRASE_PROMPT.write() {
+        *current = Some(prompt);
+    }
+}
+
+fn passphrase(path: &Path, new: bool) -> Result<String, LauncherError> {
+    let passphrase = match std::env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) => passphrase,
+        Err(_) => {
+            let prompt = PASSPHRASE_PROMPT.read().ok().and_then(|prompt| *prompt).ok_or_else(|| LauncherError::Secrets {
+                message: format!("{} is encrypted, set {} to its passphrase", path.display(), PASSPHRASE_ENV),
+                source: None,
+            })?;
+            prompt(path, new)?
+        }
+    };
+
+    if passphrase.is_empty() {
+        return Err(LauncherError::Secrets {
+            message: String::from("The passphrase can't be empty"),
+            source: None,
+        });
+    }
+
+    redact::register(&passphrase);
+
+    Ok(passphrase)
+}
+
+/// Where the device auth secrets are stored.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretBackend {
+    /// In config.json, like older versions did.
+    Plaintext,
+    /// In the keyring of the system : the Secret Service on linux, the Credential Manager on windows.
+    #[default]
+    Keyring,
+    /// In secrets.enc next to the configuration, encrypted with a passphrase.
+    EncryptedFile,
+}
+
+impl Display for SecretBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecretBackend::Plaintext => write!(f, "plaintext"),
+            SecretBackend::Keyring => write!(f, "keyring"),
+            SecretBackend::EncryptedFile => write!(f, "encrypted_file"),
+        }
+    }
+}
+
+impl SecretBackend {
+    fn parse(name: &str) -> Option<Self> {
+        [SecretBackend::Plaintext, SecretBackend::Keyring, SecretBackend::EncryptedFile]
+            .into_iter()
+            .find(|backend| backend.to_string() == name)
+    }
+}
+
+/// Reference to a secret kept in a store, written in the configuration instead of the secret : `backend:key`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SecretRef {
+    pub backend: SecretBackend,
+    pub key: String,
+}
+
+impl SecretRef {
+    pub fn new(backend: SecretBackend, key: &str) -> Self {
+        Self { backend, key: key.to_string() }
+    }
+
+    pub fn parse(reference: &str) -> Result<Self, LauncherError> {
+        reference
+            .split_once(':')
+            .and_then(|(backend, key)| Some(Self::new(SecretBackend::parse(backend)?, key)))
+            .filter(|reference| reference.backend != SecretBackend::Plaintext && !reference.key.is_empty())
+            .ok_or_else(|| LauncherError::Secrets {
+                message: format!("{} is not a valid secret reference", reference),
+                source: None,
+            })
+    }
+}
+
+impl Display for SecretRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.backend, self.key)
+    }
+}
+
+/// Storage of secrets by key.
+pub trait SecretStore: Send + Sync {
+    fn get(&self, key: &str) -> Result<Option<String>, LauncherError>;
+    fn set(&self, key: &str, secret: &str) -> Result<(), LauncherError>;
+    /// Removes the secret of `key`, doing nothing if there is none.
+    fn delete(&self, key: &str) -> Result<(), LauncherError>;
+}
+
+/// Secrets stored in the keyring of the system, one entry per key.
+pub struct KeyringStore;
+
+impl KeyringStore {
+    fn entry(key: &str) -> Result<keyring::Entry, LauncherError> {
+        keyring::Entry::new(KEYRING_SERVICE, key).map_err(|err| keyring_error(key, err))
+    }
+}
+
+fn keyring_error(key: &str, err: keyring::Error) -> LauncherError {
+    match err {
+        keyring::Error::PlatformFailure(_) | keyring::Error::NoStorageAccess(_) => LauncherError::secrets(
+            "The keyring of the system is not available, choose another secret_store in config.json",
+            err,
+        ),
+        err => LauncherError::secrets(format!("Failed to access the keyring entry {}", key), err),
+    }
+}
+
+impl SecretStore for KeyringStore {
+    fn get(&self, key: &str) -> Result<Option<String>, LauncherError> {
+        match Self::entry(key)?.get_password() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(err) => Err(keyring_error(key, err)),
+        }
+    }
+
+    fn set(&self, key: &str, secret: &str) -> Result<(), LauncherError> {
+        Self::entry(key)?.set_password(secret).map_err(|err| keyring_error(key, err))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), LauncherError> {
+        match Self::entry(key)?.delete_password() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(err) => Err(keyring_error(key, err)),
+        }
+    }
+}
+
+/// Content of secrets.enc. The secrets are a JSON object encrypted with XChaCha20-Poly1305,
+/// with a key derived from the passphrase by Argon2id.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct EncryptedFile {
+    version: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Secrets stored in a single file encrypted with a passphrase, rewritten on every change.
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    salt: Vec<u8>,
+    cipher: XChaCha20Poly1305,
+    secrets: Mutex<HashMap<String, String>>,
+}
+
+fn derive_cipher(passphrase: &str, salt: &[u8]) -> Result<XChaCha20Poly1305, LauncherError> {
+    let mut key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| LauncherError::secrets("Failed to derive the key from the passphrase", err.to_string()))?;
+
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+fn decode(path: &Path, value: &str) -> Result<Vec<u8>, LauncherError> {
+    STANDARD
+        .decode(value)
+        .map_err(|err| LauncherError::secrets(format!("{} is corrupted", path.display()), err))
+}
+
+impl EncryptedFileStore {
+    /// Opens the encrypted file at `path` with `passphrase`, or prepares a new one if there is none.
+    pub fn open(path: &Path, passphrase: &str) -> Result<Self, LauncherError> {
+        if !path.exists() {
+            let mut salt = vec![0u8; SALT_LENGTH];
+            OsRng.fill_bytes(&mut salt);
+
+            return Ok(Self {
+                path: path.to_path_buf(),
+                cipher: derive_cipher(passphrase, &salt)?,
+                salt,
+                secrets: Mutex::new(HashMap::new()),
+            });
+        }
+
+        let data_str = std::fs::read_to_string(path)
+            .map_err(|err| LauncherError::secrets(format!("Failed to read {}", path.display()), err))?;
+        let file: EncryptedFile = serde_json::from_str(&data_str)
+            .map_err(|err| LauncherError::secrets(format!("Failed to parse {}", path.display()), err))?;
+
+        let salt = decode(path, &file.salt)?;
+        let nonce = decode(path, &file.nonce)?;
+        if salt.len() != SALT_LENGTH || nonce.len() != XNonce::default().len() {
+            return Err(LauncherError::Secrets {
+                message: format!("{} is corrupted", path.display()),
+                source: None,
+            });
+        }
+
+        let cipher = derive_cipher(passphrase, &salt)?;
+        let plaintext = cipher
+            .decrypt(XNonce::from_slice(&nonce), decode(path, &file.ciphertext)?.as_ref())
+            .map_err(|_| LauncherError::Secrets {
+                message: format!("Wrong passphrase for {}", path.display()),
+                source: None,
+            })?;
+        let secrets: HashMap<String, String> = serde_json::from_slice(&plaintext)
+            .map_err(|err| LauncherError::secrets(format!("Failed to parse {}", path.display()), err))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            salt,
+            cipher,
+            secrets: Mutex::new(secrets),
+        })
+    }
+
+    fn write(&self, secrets: &HashMap<String, String>) -> Result<(), LauncherError> {
+        let plaintext = serde_json::to_vec(secrets)
+            .map_err(|err| LauncherError::secrets("Failed to serialize the secrets", err))?;
+        //a new nonce for every write, reusing one with the same key would leak the secrets
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_ref())
+            .map_err(|err| LauncherError::secrets("Failed to encrypt the secrets", err.to_string()))?;
+
+        let file = EncryptedFile {
+            version: 1,
+            salt: STANDARD.encode(&self.salt),
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+        };
+        let data = serde_json::to_string_pretty(&file)
+            .map_err(|err| LauncherError::secrets("Failed to serialize the secrets", err))?;
+
+        config::write_file(&self.path, &data)
+    }
+
+    fn update(&self, change: impl FnOnce(&mut HashMap<String, String>) -> bool) -> Result<(), LauncherError> {
+        let mut secrets = self.secrets.lock().map_err(|_| LauncherError::Secrets {
+            message: String::from("The secret store has been poisoned"),
+            source: None,
+        })?;
+
+        if change(&mut secrets) {
+            self.write(&secrets)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl SecretStore for EncryptedFileStore {
+    fn get(&self, key: &str) -> Result<Option<String>, LauncherError> {
+        Ok(self.secrets.lock().ok().and_then(|secrets| secrets.get(key).cloned()))
+    }
+
+    fn set(&self, key: &str, secret: &str) -> Result<(), LauncherError> {
+        self.update(|secrets| secrets.insert(key.to_string(), secret.to_string()).as_deref() != Some(secret))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), LauncherError> {
+        self.update(|secrets| secrets.remove(key).is_some())
+    }
+}
+
+/// Data too large for the keyring, encrypted with a random key kept in a secret store.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SealedData {
+    version: u32,
+    /// Reference of the key in its store.
+    key: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+const SEALED_KEY_LENGTH: usize = 32;
+
+/// Stores of a configuration, opened the first time they are used so the passphrase is only asked for when needed.
+/// Clones share the opened stores.
+#[derive(Clone, Default)]
+pub struct SecretStores {
+    encrypted_file: PathBuf,
+    opened: Arc<Mutex<HashMap<SecretBackend, Arc<dyn SecretStore>>>>,
+}
+
+impl SecretStores {
+    /// Stores of the configuration at `path`.
+    pub fn new(path: &Path) -> Self {
+        Self {
+            encrypted_file: path.with_file_name(SECRETS_FILE),
+            opened: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Store of `backend`, None for plaintext.
+    pub fn get(&self, backend: SecretBackend) -> Result<Option<Arc<dyn SecretStore>>, LauncherError> {
+        let mut opened = self.opened.lock().map_err(|_| LauncherError::Secrets {
+            message: String::from("The secret stores have been poisoned"),
+            source: None,
+        })?;
+
+        if let Some(store) = opened.get(&backend) {
+            return Ok(Some(store.clone()));
+        }
+
+        let store: Arc<dyn SecretStore> = match backend {
+            SecretBackend::Plaintext => return Ok(None),
+            SecretBackend::Keyring => Arc::new(KeyringStore),
+            SecretBackend::EncryptedFile => {
+                let path = &self.encrypted_file;
+                let passphrase = passphrase(path, !path.exists())?;
+                Arc::new(EncryptedFileStore::open(path, &passphrase)?)
+            }
+        };
+
+        tracing::debug!(%backend, "Opened the secret store");
+        opened.insert(backend, store.clone());
+
+        Ok(Some(store))
+    }
+
+    /// Reads the secret a reference points to.
+    pub fn read(&self, reference: &SecretRef) -> Result<String, LauncherError> {
+        let store = self.get(reference.backend)?.ok_or_else(|| LauncherError::Secrets {
+            message: format!("{} does not point to a secret store", reference),
+            source: None,
+        })?;
+
+        store.get(&reference.key)?.ok_or_else(|| LauncherError::Secrets {
+            message: format!("The secret {} is missing from its store", reference),
+            source: None,
+        })
+    }
+
+    /// Removes the secret a reference points to.
+    pub fn delete(&self, reference: &SecretRef) -> Result<(), LauncherError> {
+        match self.get(reference.backend)? {
+            Some(store) => store.delete(&reference.key),
+            None => Ok(()),
+        }
+    }
+
+    /// Creates a random key in the store of `backend` to seal data with, None for plaintext.
+    pub fn create_key(&self, backend: SecretBackend, name: &str) -> Result<Option<SecretRef>, LauncherError> {
+        let Some(store) = self.get(backend)? else {
+            return Ok(None);
+        };
+
+        let mut id = [0u8; 8];
+        OsRng.fill_bytes(&mut id);
+        let id: String = id.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let mut key = [0u8; SEALED_KEY_LENGTH];
+        OsRng.fill_bytes(&mut key);
+
+        let reference = SecretRef::new(backend, &format!("{}:{}", name, id));
+        store.set(&reference.key, &STANDARD.encode(key))?;
+
+        Ok(Some(reference))
+    }
+
+    fn sealing_cipher(&self, key: &SecretRef) -> Result<XChaCha20Poly1305, LauncherError> {
+        let key = STANDARD
+            .decode(self.read(key)?)
+            .map_err(|err| LauncherError::secrets(format!("The key {} is corrupted", key), err))?;
+
+        XChaCha20Poly1305::new_from_slice(&key)
+            .map_err(|err| LauncherError::secrets("The sealing key is corrupted", err.to_string()))
+    }
+
+    /// Encrypts `data` with a key created by `create_key`.
+    pub fn seal(&self, key: &SecretRef, data: &str) -> Result<String, LauncherError> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .sealing_cipher(key)?
+            .encrypt(&nonce, data.as_bytes())
+            .map_err(|err| LauncherError::secrets("Failed to encrypt the data", err.to_string()))?;
+
+        let sealed = SealedData {
+            version: 1,
+            key: key.to_string(),
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+        };
+
+        serde_json::to_string_pretty(&sealed).map_err(|err| LauncherError::secrets("Failed to serialize the sealed data", err))
+    }
+
+    /// Decrypts the data read from `path` if it has been sealed, and returns it with the reference of its key.
+    pub fn unseal(&self, path: &Path, data_str: &str) -> Result<Option<(String, SecretRef)>, LauncherError> {
+        let Ok(sealed) = serde_json::from_str::<SealedData>(data_str) else {
+            return Ok(None);
+        };
+
+        let key = SecretRef::parse(&sealed.key)?;
+        let nonce = decode(path, &sealed.nonce)?;
+        if nonce.len() != XNonce::default().len() {
+            return Err(LauncherError::Secrets {
+                message: format!("{} is corrupted", path.display()),
+                source: None,
+            });
+        }
+
+        let plaintext = self
+            .sealing_cipher(&key)?
+            .decrypt(XNonce::from_slice(&nonce), decode(path, &sealed.ciphertext)?.as_ref())
+            .map_err(|_| LauncherError::Secrets {
+                message: format!("{} can't be decrypted with {}", path.display(), key),
+                source: None,
+            })?;
+        let data = String::from_utf8(plaintext)
+            .map_err(|err| LauncherError::secrets(format!("{} is corrupted", path.display()), err))?;
+
+        Ok(Some((data, key)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temporary_folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("fnl-secrets-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    /// Stores of a configuration in `folder`, with the encrypted file already opened so no passphrase is asked for.
+    fn stores(folder: &Path) -> SecretStores {
+        let stores = SecretStores::new(&folder.join("config.json"));
+        let store = EncryptedFileStore::open(&stores.encrypted_file, "passphrase").unwrap();
+        stores.opened.lock().unwrap().insert(SecretBackend::EncryptedFile, Arc::new(store));
+        stores
+    }
+
+    /// Flips a byte of the base64 `field` of a sealed or encrypted JSON file.
+    fn tamper(data_str: &str, field: &str) -> String {
+        let mut value: serde_json::Value = serde_json::from_str(data_str).unwrap();
+        let mut bytes = STANDARD.decode(value[field].as_str().unwrap()).unwrap();
+        bytes[0] ^= 1;
+        value[field] = serde_json::json!(STANDARD.encode(bytes));
+        value.to_string()
+    }
+
+    #[test]
+    fn parses_references() {
+        let reference = SecretRef::parse("keyring:abc:device").unwrap();
+        assert_eq!(reference, SecretRef::new(SecretBackend::Keyring, "abc:device"));
+        assert_eq!(reference.to_string(), "keyring:abc:device");
+
+        let reference = SecretRef::parse("encrypted_file:sessions:0123").unwrap();
+        assert_eq!(reference.backend, SecretBackend::EncryptedFile);
+
+        assert!(SecretRef::parse("plaintext:abc").is_err());
+        assert!(SecretRef::parse("vault:abc").is_err());
+        assert!(SecretRef::parse("keyring:").is_err());
+        assert!(SecretRef::parse("keyring").is_err());
+    }
+
+    #[test]
+    fn reopens_encrypted_file() {
+        let path = temporary_folder("reopen").join(SECRETS_FILE);
+
+        let store = EncryptedFileStore::open(&path, "passphrase").unwrap();
+        store.set("abc:device", "secret").unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("secret\""));
+
+        let store = EncryptedFileStore::open(&path, "passphrase").unwrap();
+        assert_eq!(store.get("abc:device").unwrap().as_deref(), Some("secret"));
+
+        store.delete("abc:device").unwrap();
+        let store = EncryptedFileStore::open(&path, "passphrase").unwrap();
+        assert_eq!(store.get("abc:device").unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_wrong_passphrase() {
+        let path = temporary_folder("passphrase").join(SECRETS_FILE);
+        EncryptedFileStore::open(&path, "passphrase").unwrap().set("abc:device", "secret").unwrap();
+
+        assert!(EncryptedFileStore::open(&path, "another passphrase").is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_file() {
+        let path = temporary_folder("tampered").join(SECRETS_FILE);
+        EncryptedFileStore::open(&path, "passphrase").unwrap().set("abc:device", "secret").unwrap();
+
+        let data_str = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, tamper(&data_str, "ciphertext")).unwrap();
+
+        assert!(EncryptedFileStore::open(&path, "passphrase").is_err());
+    }
+
+    #[test]
+    fn seals_and_unseals() {
+        let folder = temporary_folder("seal");
+        let stores = stores(&folder);
+        let path = folder.join("sessions.json");
+
+        let key = stores.create_key(SecretBackend::EncryptedFile, "sessions").unwrap().unwrap();
+        let sealed = stores.seal(&key, r#"{"sessions":{}}"#).unwrap();
+        assert!(!sealed.contains("sessions\":"));
+
+        let (data, unsealed_key) = stores.unseal(&path, &sealed).unwrap().unwrap();
+        assert_eq!(data, r#"{"sessions":{}}"#);
+        assert_eq!(unsealed_key, key);
+
+        //a new nonce for every seal
+        assert_ne!(stores.seal(&key, r#"{"sessions":{}}"#).unwrap(), sealed);
+
+        assert!(stores.create_key(SecretBackend::Plaintext, "sessions").unwrap().is_none());
+        assert!(stores.unseal(&path, r#"{"sessions":{}}"#).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_tampered_seal() {
+        let folder = temporary_folder("tampered-seal");
+        let stores = stores(&folder);
+        let path = folder.join("sessions.json");
+
+        let key = stores.create_key(SecretBackend::EncryptedFile, "sessions").unwrap().unwrap();
+        let sealed = stores.seal(&key, r#"{"sessions":{}}"#).unwrap();
+
+        assert!(stores.unseal(&path, &tamper(&sealed, "ciphertext")).is_err());
+        assert!(stores.unseal(&path, &tamper(&sealed, "nonce")).is_err());
+
+        stores.delete(&key).unwrap();
+        assert!(stores.unseal(&path, &sealed).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use chrono::Duration;
+
+use crate::{
+    config::DeviceAuth,
+    error::LauncherError,
+    epic::{
+        self, AccountDetails, HasClient, HasIdentity, HasRefreshToken, HasToken, LoginClients, Token,
+    },
+    rest::{EpicContext, EpicError},
+};
+
+/// Access tokens expiring in less than this many seconds are renewed before being used.
+const RENEWAL_MARGIN_SECONDS: i64 = 5 * 60;
+
+/// Keeps an `AccountDetails` usable over time : the access token is renewed with the refresh token
+/// before it expires, and the device auth is only used once the refresh token has expired too.
+pub struct Session {
+    context: EpicContext,
+    details: AccountDetails,
+    clients: LoginClients,
+    device_auth: Option<DeviceAuth>,
+}
+
+impl Session {
+    /// `details` must have been issued by the launch client of `clients`, refresh tokens are bound to it.
+    pub fn new(context: EpicContext, details: AccountDetails, clients: LoginClients, device_auth: Option<DeviceAuth>) -> Self {
+        Self {
+            context,
+            details,
+            clients,
+            device_auth,
+        }
+    }
+
+    pub fn details(&self) -> &AccountDetails {
+        &self.details
+    }
+
+    /// Renews the session if the access token is about to expire, returns true if it has been renewed.
+    pub async fn renew_if_needed(&mut self) -> Result<bool, LauncherError> {
+        if !self
+            .details
+            .get_access_token()
+            .expires_within(Duration::seconds(RENEWAL_MARGIN_SECONDS))
+        {
+            return Ok(false);
+        }
+
+        self.renew().await?;
+
+        Ok(true)
+    }
+
+    /// Renews the session with the refresh token, or with the device auth if the refresh token
+    /// has expired or has been revoked server-side.
+    pub async fn renew(&mut self) -> Result<(), LauncherError> {
+        let refresh_token = self.details.get_refresh_token();
+
+        if !refresh_token.is_expired() {
+            match epic::login_with_refresh_token(&self.context, &refresh_token.token, &self.clients.launch).await {
+                Ok(details) => {
+                    self.details = details;
+                    return Ok(());
+                }
+                Err(err) if err.is_invalid_credentials() && self.device_auth.is_some() => {
+                    tracing::warn!(account_id = %self.details.account_id, "The refresh token has been revoked, logging in with the device auth");
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        self.details = self.device_auth_login().await?;
+
+        Ok(())
+    }
+
+    async fn device_auth_login(&self) -> Result<AccountDetails, LauncherError> {
+        let Some(device_auth) = &self.device_auth else {
+            return Err(EpicError::Expired.into());
+        };
+
+        let details = epic::login_with_device_auth(&self.context, device_auth, &self.clients.onboarding).await?;
+
+        if details.client_id == self.clients.launch.id {
+            Ok(details)
+        } else {
+            epic::exchange_to(&self.context, &details, &self.clients.launch).await
+        }
+    }
+}
+
+impl HasToken for Session {
+    fn get_access_token(&self) -> Token {
+        self.details.get_access_token()
+    }
+}
+
+impl HasRefreshToken for Session {
+    fn get_refresh_token(&self) -> Token {
+        self.details.get_refresh_token()
+    }
+}
+
+impl HasClient for Session {
+    fn get_client_id(&self) -> &str {
+        self.details.get_client_id()
+    }
+}
+
+impl HasIdentity for Session {
+    fn get_display_name(&self) -> &str {
+        self.details.get_display_name()
+    }
+
+    fn get_account_id(&self) -> &str {
+        self.details.get_account_id()
+    }
+}